@@ -0,0 +1,130 @@
+//! Bundles the directory given by `STHUB_EMBED_DIR` into the binary when the `embed` feature is
+//! enabled, along with the content hash and the compressed variants of every file.
+
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+const EMBED_DIR_VAR: &str = "STHUB_EMBED_DIR";
+/// Hex digits of the SHA-256 kept in the ETag, as for the content ETags of the static hub.
+const ETAG_HASH_LENGTH: usize = 32;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed={EMBED_DIR_VAR}");
+
+    let out_dir = PathBuf::from(env::var("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    let mut table = String::from("pub static ASSETS: &[EmbeddedAsset] = &[\n");
+
+    if env::var_os("CARGO_FEATURE_EMBED").is_some() {
+        let dir = env::var(EMBED_DIR_VAR).unwrap_or_else(|_| {
+            panic!("the `embed` feature requires {EMBED_DIR_VAR} to point to the static directory")
+        });
+        let root = fs::canonicalize(&dir)
+            .unwrap_or_else(|e| panic!("failed to read {EMBED_DIR_VAR} '{dir}': {e}"));
+        println!("cargo:rerun-if-changed={}", root.display());
+
+        let variants = out_dir.join("embedded");
+        fs::create_dir_all(&variants).expect("failed to create the embedded variants directory");
+
+        let mut files = Vec::new();
+        collect_files(&root, &mut files);
+        // sorted by relative path, so that assets can be looked up with a binary search
+        let mut files: Vec<(String, PathBuf)> = files
+            .into_iter()
+            .map(|f| {
+                let relative = f
+                    .strip_prefix(&root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                (relative, f)
+            })
+            .collect();
+        files.sort();
+
+        for (index, (relative, file)) in files.iter().enumerate() {
+            let data = fs::read(file)
+                .unwrap_or_else(|e| panic!("failed to read '{}': {e}", file.display()));
+
+            let hash: String = Sha256::digest(&data)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect();
+
+            let gzip = variant(&variants, index, "gz", &data, gzip);
+            let brotli = variant(&variants, index, "br", &data, brotli);
+
+            table.push_str(&format!(
+                "    EmbeddedAsset {{ path: {relative:?}, data: include_bytes!({:?}), gzip: {gzip}, brotli: {brotli}, etag: {:?} }},\n",
+                file.display().to_string(),
+                &hash[..ETAG_HASH_LENGTH],
+            ));
+        }
+    }
+
+    table.push_str("];\n");
+    fs::write(out_dir.join("embedded_assets.rs"), table)
+        .expect("failed to write the embedded assets");
+}
+
+/// Every file of a directory, dotfiles excluded.
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let entries =
+        fs::read_dir(dir).unwrap_or_else(|e| panic!("failed to read '{}': {e}", dir.display()));
+
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+
+        let path = entry.path();
+        if path.is_dir() {
+            collect_files(&path, files);
+        } else if path.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+/// Writes the compressed variant of a file and returns the expression including it,
+/// `None` when compressing does not make it smaller.
+fn variant(
+    dir: &Path,
+    index: usize,
+    extension: &str,
+    data: &[u8],
+    compress: fn(&[u8]) -> Vec<u8>,
+) -> String {
+    let compressed = compress(data);
+    if compressed.len() >= data.len() {
+        return "None".to_string();
+    }
+
+    let path = dir.join(format!("{index}.{extension}"));
+    fs::write(&path, compressed).expect("failed to write a compressed variant");
+    format!("Some(include_bytes!({:?}))", path.display().to_string())
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder
+        .write_all(data)
+        .expect("failed to compress with gzip");
+    encoder.finish().expect("failed to compress with gzip")
+}
+
+fn brotli(data: &[u8]) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    {
+        let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+        encoder
+            .write_all(data)
+            .expect("failed to compress with brotli");
+    }
+    compressed
+}
@@ -1,8 +1,47 @@
-use clap::{Parser, command};
+use clap::{Parser, Subcommand, command};
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
     #[arg(short, long)]
     pub configuration_path: Option<String>,
+    /// Validate the configuration and report every invalid rewrite rule, without starting the server
+    #[arg(long)]
+    pub check: bool,
+    /// Watch the static files and the configuration, reloading the browsers on change, without caching
+    #[arg(long)]
+    pub dev: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum Command {
+    /// Print, for each URL, the rewrite conditions that matched, the rewritten path and the hub serving it
+    TestRewrite {
+        /// URLs or paths to test (e.g. `/about` or `http://localhost:8080/about?lang=fr`)
+        #[arg(required = true)]
+        urls: Vec<String>,
+        /// Host of the site serving the URLs (default: the host of each URL, the instance hubs serving the others)
+        #[arg(long)]
+        site: Option<String>,
+    },
+    /// List the releases of the static hub, switch to one or roll back to the previous one
+    Release {
+        #[command(subcommand)]
+        command: ReleaseCommand,
+        /// Host of the site whose releases to manage (default: the instance hubs)
+        #[arg(long, global = true)]
+        site: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum ReleaseCommand {
+    /// List the releases, `*` marking the current one and `-` the previous one
+    List,
+    /// Serve a release from now on, in-flight requests completing against the previous one
+    Switch { release: String },
+    /// Serve the previous release again
+    Rollback,
 }
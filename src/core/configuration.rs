@@ -6,6 +6,43 @@ pub struct Configuration {
     pub network: Option<NetworkConfiguration>,
     pub global: Option<GlobalConfiguration>,
     pub hubs: Option<ConfigurationHubs>,
+    pub redirects: Option<Vec<RedirectConfiguration>>,
+    pub admin: Option<AdminConfiguration>,
+    /// Virtual hosts with their own hubs, the hubs above serving the other hosts.
+    pub sites: Option<Vec<SiteConfiguration>>,
+}
+
+impl Configuration {
+    /// The configuration of a virtual host, served on the network of the instance.
+    pub fn site(&self, site: &SiteConfiguration) -> Configuration {
+        Configuration {
+            network: self.network.clone(),
+            global: site.global.clone(),
+            hubs: site.hubs.clone(),
+            redirects: site.redirects.clone(),
+            admin: None,
+            sites: None,
+        }
+    }
+}
+
+/// A site served for some hosts, configured like the instance.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SiteConfiguration {
+    /// The host names of the site, `*.` matching any subdomain and `*` any host.
+    pub hosts: Vec<String>,
+    pub global: Option<GlobalConfiguration>,
+    pub hubs: Option<ConfigurationHubs>,
+    pub redirects: Option<Vec<RedirectConfiguration>>,
+}
+
+/// The administration API, disabled unless configured.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AdminConfiguration {
+    /// Where the API is mounted (default: `/__sthub/admin`).
+    pub remote_path: Option<String>,
+    /// The bearer token of the requests, `STHUB_ADMIN_TOKEN` being used when not set.
+    pub token: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -22,11 +59,24 @@ pub struct ConfigurationHubConfiguration {
     pub cache: Option<bool>,
     pub headers: Option<HashMap<String, String>>,
     pub providers: Option<ConfigurationHubProviders>,
+    pub compression: Option<CompressionConfiguration>,
+    pub error_pages: Option<HashMap<String, ErrorPageConfiguration>>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct GlobalConfiguration {
     pub headers: Option<HashMap<String, String>>,
+    /// Error pages of the hubs without one for the status.
+    pub error_pages: Option<HashMap<String, ErrorPageConfiguration>>,
+}
+
+/// The page replacing the body of error responses, keyed by status (`404`), class (`5xx`) or range (`500-504`).
+#[derive(Deserialize, Debug, Clone)]
+pub struct ErrorPageConfiguration {
+    /// A file of the static hub directory.
+    pub file: Option<String>,
+    /// An inline page, where `{status}`, `{reason}` and `{path}` are replaced.
+    pub template: Option<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -50,13 +100,266 @@ pub struct StaticHubConfiguration {
     pub remote_path: Option<String>,
     pub path: Option<String>,
     pub rewrite_rules: Option<String>,
+    pub rewrite_rules_file: Option<String>,
     pub headers: Option<HashMap<String, String>>,
+    /// Serve `fallback` (or `index.html`) for application routes without rewrite rules.
+    pub spa: Option<bool>,
+    pub fallback: Option<String>,
+    /// Serve `.br`, `.zst` or `.gz` siblings of the requested files when accepted by the client.
+    pub precompressed: Option<bool>,
+    pub compression: Option<CompressionConfiguration>,
+    /// Strong ETags computed from the file contents instead of their modification time and size.
+    pub content_etag: Option<bool>,
+    /// Headers applied to the files matching a pattern, after `headers`.
+    pub cache_rules: Option<Vec<CacheRuleConfiguration>>,
+    pub cache_rules_mode: Option<CacheRulesMode>,
+    pub error_pages: Option<HashMap<String, ErrorPageConfiguration>>,
+    pub listing: Option<ListingConfiguration>,
+    /// Serve the assets bundled into the binary with the `embed` feature instead of `path`.
+    pub embedded: Option<bool>,
+    /// Where the files are read from, `path` on the local filesystem by default.
+    pub storage: Option<StorageConfiguration>,
+    /// The paths never served, dotfiles by default.
+    pub access: Option<AccessConfiguration>,
+    /// Serve `about.html` for `/about`.
+    pub clean_urls: Option<bool>,
+    pub trailing_slash: Option<TrailingSlash>,
+    /// Look up the files regardless of the case of the path, when not found as is.
+    pub case_insensitive: Option<bool>,
+    /// The index documents of directories, by order of preference (default: `index.html`).
+    pub index: Option<Vec<String>>,
+    /// Content types by file extension, taking precedence over the built-in ones.
+    pub mime_types: Option<HashMap<String, String>>,
+    /// The charset declared for text content types, `none` to declare none (default: `utf-8`).
+    pub charset: Option<String>,
+    /// Inject the environment configuration tree into the HTML documents.
+    pub inject_env: Option<InjectEnvConfiguration>,
+    /// Substitute environment variables for the placeholders of some files.
+    pub substitute_env: Option<SubstituteEnvConfiguration>,
+    /// A directory of versioned releases, whose `current` one is served instead of `path`.
+    pub releases: Option<String>,
+    /// Subresource integrity hashes of some assets, in a manifest and the HTML documents.
+    pub integrity: Option<IntegrityConfiguration>,
+    /// Resize and convert the images on demand, with the `images` feature.
+    pub images: Option<ImagesConfiguration>,
+    /// Serve the variant of the documents in the language of the client.
+    pub locales: Option<LocalesConfiguration>,
+}
+
+/// The languages of the static content variants, and how clients override the negotiated one.
+#[derive(Deserialize, Debug, Clone)]
+pub struct LocalesConfiguration {
+    /// The language tags of the variants, the first one being the default language.
+    pub languages: Vec<String>,
+    /// The cookie holding the language chosen by the user (default: `lang`), `""` to ignore it.
+    pub cookie: Option<String>,
+    /// The query parameter holding the language chosen by the user (default: `lang`), `""` to
+    /// ignore it.
+    pub query: Option<String>,
+}
+
+/// The variants of the images that can be requested, and where they are cached.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ImagesConfiguration {
+    /// The widths the images can be resized to, other widths being refused.
+    pub widths: Vec<u32>,
+    /// The formats the images can be converted to (default: every available one).
+    pub formats: Option<Vec<String>>,
+    /// The quality of the lossy formats, from 1 to 100 (default: `80`).
+    pub quality: Option<u8>,
+    /// Where the derived images are stored (default: a directory of the hub in the temporary one).
+    pub cache_dir: Option<String>,
+}
+
+/// The assets hashed for subresource integrity, and where the hashes are exposed.
+#[derive(Deserialize, Debug, Clone)]
+pub struct IntegrityConfiguration {
+    /// Globs of the hashed files (default: `*.js` and `*.css`).
+    pub files: Option<Vec<String>>,
+    /// The path of the JSON manifest, relative to the remote path, empty to disable it
+    /// (default: `/integrity.json`).
+    pub manifest: Option<String>,
+    /// Add `integrity` attributes to the `<script>` and `<link>` tags of the HTML documents
+    /// (default: `true`).
+    pub attributes: Option<bool>,
+}
+
+/// How the environment configuration tree is injected into the HTML documents.
+#[derive(Deserialize, Debug, Clone)]
+pub struct InjectEnvConfiguration {
+    /// The global variable holding the tree (default: `__ENV__`).
+    pub variable: Option<String>,
+    /// The token replaced by the script, instead of inserting it before `</head>`.
+    pub placeholder: Option<String>,
+    /// Give the script a CSP nonce, substituted for `{nonce}` in the static hub headers.
+    pub nonce: Option<bool>,
+}
+
+/// The files whose placeholders are replaced by environment variables, and their syntax.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SubstituteEnvConfiguration {
+    /// Globs of the substituted files.
+    pub files: Vec<String>,
+    /// Opens a placeholder (default: `${`).
+    pub start: Option<String>,
+    /// Closes a placeholder (default: `}`).
+    pub end: Option<String>,
+    /// What becomes of the placeholders of unset variables (default: `keep`).
+    pub missing: Option<MissingVariable>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MissingVariable {
+    Keep,
+    Empty,
+}
+
+/// Whether paths are redirected to their form with or without trailing slash.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrailingSlash {
+    Add,
+    Remove,
+    Leave,
+}
+
+/// The paths of the static hub that are not served, and how symlinks are followed.
+#[derive(Deserialize, Debug, Clone)]
+pub struct AccessConfiguration {
+    /// Whether paths with a segment starting with a dot are served (default: `deny`).
+    pub dotfiles: Option<DotfilesPolicy>,
+    /// Globs of the denied paths, e.g. `*.sql` or `/private/`.
+    pub deny: Option<Vec<String>>,
+    /// Globs of the paths served even when denied, e.g. `/.well-known/`.
+    pub allow: Option<Vec<String>>,
+    pub symlinks: Option<SymlinkPolicy>,
+    /// The status of the denied requests, 404 (default) or 403.
+    pub status: Option<u16>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DotfilesPolicy {
+    Allow,
+    Deny,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SymlinkPolicy {
+    Follow,
+    Deny,
+    /// Only symlinks resolving within the hub directory are followed.
+    WithinRoot,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageKind {
+    Filesystem,
+    S3,
+}
+
+/// The storage backend of the static hub.
+#[derive(Deserialize, Debug, Clone)]
+pub struct StorageConfiguration {
+    pub kind: StorageKind,
+    /// The object store URL, e.g. `http://127.0.0.1:9000` for a local MinIO.
+    pub endpoint: Option<String>,
+    pub bucket: Option<String>,
+    /// The key prefix the hub files are stored under.
+    pub prefix: Option<String>,
+    pub region: Option<String>,
+    /// Requests are signed when set, `AWS_ACCESS_KEY_ID` being used otherwise.
+    pub access_key_id: Option<String>,
+    /// `AWS_SECRET_ACCESS_KEY` being used when not set.
+    pub secret_access_key: Option<String>,
+    /// Seconds the object store has to answer a request (default: 30).
+    pub timeout: Option<u64>,
+}
+
+/// Listing of the static hub directories without index document.
+#[derive(Deserialize, Debug, Clone)]
+pub struct ListingConfiguration {
+    /// The listed directories, relative to the remote path, with their subdirectories (default: all).
+    pub paths: Option<Vec<String>>,
+    /// List the entries starting with a dot.
+    pub show_hidden: Option<bool>,
+    pub max_entries: Option<usize>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct UpstreamConfiguration {
     pub target: Option<String>,
     pub remote_path: Option<String>,
+    pub rewrite_rules: Option<String>,
+    pub rewrite_rules_file: Option<String>,
+    /// Responses already encoded by the upstream are never compressed again.
+    pub compression: Option<CompressionConfiguration>,
+    pub error_pages: Option<HashMap<String, ErrorPageConfiguration>>,
+}
+
+/// On-the-fly compression of the responses of a hub.
+#[derive(Deserialize, Debug, Clone)]
+pub struct CompressionConfiguration {
+    /// The encodings offered (`br`, `zstd`, `gzip`), by order of preference.
+    pub encodings: Option<Vec<String>>,
+    /// Responses smaller than this size (in bytes) are sent uncompressed.
+    pub min_size: Option<usize>,
+    /// The compressed content types, `text/*` matches any subtype.
+    pub mime_types: Option<Vec<String>>,
+    /// The compression level, capped to the maximum of each encoding.
+    pub level: Option<u32>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheRuleConfiguration {
+    /// The glob, regular expression or extension matched against the path relative to the hub.
+    pub pattern: String,
+    pub kind: Option<CacheRuleKind>,
+    pub cache_control: Option<String>,
+    /// The `Expires` header, in seconds from the response time.
+    pub expires: Option<u64>,
+    pub headers: Option<HashMap<String, String>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheRuleKind {
+    Glob,
+    Regex,
+    Extension,
+}
+
+/// Whether only the first matching cache rule applies, or every matching one.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheRulesMode {
+    First,
+    Merge,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RedirectConfiguration {
+    /// The path (exact or prefix) or regular expression matched against the request path.
+    pub from: String,
+    /// The redirect target, regex captures are available as `$1` or `${name}`.
+    pub to: String,
+    pub kind: Option<RedirectKind>,
+    /// One of 301, 302, 307 or 308.
+    pub status: Option<u16>,
+    pub preserve_query: Option<bool>,
+    /// Only redirect requests for this host, `*.` matches any subdomain.
+    pub host: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedirectKind {
+    Exact,
+    Prefix,
+    Regex,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -66,12 +369,10 @@ pub struct NetworkConfiguration {
 }
 
 pub async fn load_configuration(path: &str) -> std::io::Result<Configuration> {
-    let res = tokio::fs::read_to_string(path)
-        .await
-        .expect("Failed to read configuration file");
+    let res = tokio::fs::read_to_string(path).await?;
 
-    let config = serde_yaml::from_str::<Configuration>(&res).unwrap();
-    Ok(config)
+    serde_yaml::from_str::<Configuration>(&res)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
 }
 
 #[cfg(test)]
@@ -0,0 +1,469 @@
+use actix_rewrite::Engine;
+use regex::{Regex, RegexBuilder};
+use std::{fmt, io, path::Path};
+
+use crate::core::configuration::Configuration;
+
+const COMMENT_PREFIX: char = '#';
+const CONTAINER_PREFIX: char = '<';
+const CONDITION_DIRECTIVE: &str = "RewriteCond";
+const RULE_DIRECTIVE: &str = "RewriteRule";
+/// Test strings of the conditions checking the requested file.
+const REQUESTED_FILE: [&str; 2] = ["%{DOCUMENT_ROOT}%{REQUEST_URI}", "%{REQUEST_FILENAME}"];
+/// Neutral rule used to validate conditions when the rule they belong to is itself invalid.
+const NEUTRAL_RULE: &str = "RewriteRule ^ /";
+
+/// A single directive of a rule set, along with where it was declared.
+#[derive(Debug, Clone, PartialEq)]
+struct Directive {
+    origin: String,
+    line: usize,
+    column: usize,
+    text: String,
+}
+
+impl Directive {
+    fn is(&self, directive: &str) -> bool {
+        self.text.split_whitespace().next() == Some(directive)
+    }
+
+    fn error(&self, message: impl Into<String>) -> RuleError {
+        RuleError {
+            origin: self.origin.clone(),
+            line: self.line,
+            column: self.column,
+            message: message.into(),
+        }
+    }
+}
+
+/// An invalid directive, located by its origin (file path or configuration key), line and column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleError {
+    pub origin: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.origin, self.line, self.column, self.message
+        )
+    }
+}
+
+/// Apache-like rewrite directives, stripped of comments, blank lines and `<IfModule>` containers.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    directives: Vec<Directive>,
+}
+
+impl RuleSet {
+    /// Parses rules from their source, `origin` is only used to locate errors.
+    pub fn parse(origin: &str, source: &str) -> Self {
+        let directives = source
+            .lines()
+            .enumerate()
+            .filter_map(|(index, line)| {
+                let text = line.trim();
+                if text.is_empty()
+                    || text.starts_with(COMMENT_PREFIX)
+                    || text.starts_with(CONTAINER_PREFIX)
+                {
+                    return None;
+                }
+                Some(Directive {
+                    origin: origin.to_string(),
+                    line: index + 1,
+                    column: line.len() - line.trim_start().len() + 1,
+                    text: text.to_string(),
+                })
+            })
+            .collect();
+
+        Self { directives }
+    }
+
+    /// Loads the rules of a hub from its inline `rewrite_rules` and its `rewrite_rules_file`,
+    /// inline rules come first when both are set.
+    pub fn load(hub: &str, inline: Option<&String>, file: Option<&String>) -> io::Result<Self> {
+        let mut set = inline
+            .map(|r| Self::parse(&format!("hubs.{hub}.rewrite_rules"), r))
+            .unwrap_or_default();
+
+        if let Some(path) = file {
+            let source = std::fs::read_to_string(path).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!("failed to read rewrite rules file '{path}': {e}"),
+                )
+            })?;
+            set.directives.extend(Self::parse(path, &source).directives);
+        }
+
+        Ok(set)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directives.is_empty()
+    }
+
+    /// The conditions of the rule set, along with their `origin:line` location and a rule set
+    /// rewriting to `target` when the engine gets to the condition and it matches: the
+    /// directives declared before the condition, the ones of its rule included, followed by the
+    /// pattern of its rule, so that back-references resolve as they do when serving.
+    pub fn condition_probes(&self, target: &str) -> Vec<(String, &str, RuleSet)> {
+        let mut probes = Vec::new();
+        let mut conditions = Vec::new();
+
+        for (index, directive) in self.directives.iter().enumerate() {
+            if directive.is(CONDITION_DIRECTIVE) {
+                conditions.push(index);
+                continue;
+            }
+            if !directive.is(RULE_DIRECTIVE) {
+                conditions.clear();
+                continue;
+            }
+
+            for i in conditions.drain(..) {
+                let mut directives = self.directives[..=i].to_vec();
+                directives.push(Directive {
+                    text: probe_rule(&directive.text, target),
+                    ..directive.clone()
+                });
+                let condition = &self.directives[i];
+                probes.push((
+                    format!("{}:{}", condition.origin, condition.line),
+                    condition.text.as_str(),
+                    RuleSet { directives },
+                ));
+            }
+        }
+
+        probes
+    }
+
+    /// The patterns of the rules that may rewrite the path of an existing file. When the engine
+    /// `checks_files`, the rules requiring the requested file to be missing (`!-f`) are left out.
+    /// Negated patterns, and the ones the `regex` crate does not support, match every path.
+    pub fn file_rewrites(&self, checks_files: bool) -> RulePatterns {
+        let mut patterns = Vec::new();
+        // whether the conditions so far require a missing file, and the last one is or-ed
+        let (mut missing_file, mut or) = (false, false);
+
+        for directive in &self.directives {
+            if directive.is(CONDITION_DIRECTIVE) {
+                let mut tokens = directive.text.split_whitespace().skip(1);
+                let requires_missing = REQUESTED_FILE.contains(&tokens.next().unwrap_or_default())
+                    && tokens.next() == Some("!-f");
+                let or_ed = has_flag(&directive.text, "OR");
+                missing_file |= checks_files && requires_missing && !or && !or_ed;
+                or = or_ed;
+                continue;
+            }
+            if !directive.is(RULE_DIRECTIVE) {
+                (missing_file, or) = (false, false);
+                continue;
+            }
+
+            if !std::mem::take(&mut missing_file) {
+                let (pattern, nocase) = rule_pattern(&directive.text);
+                patterns.push(match pattern.starts_with('!') {
+                    true => None,
+                    false => RegexBuilder::new(pattern)
+                        .case_insensitive(nocase)
+                        .build()
+                        .ok(),
+                });
+            }
+            or = false;
+        }
+
+        RulePatterns(patterns)
+    }
+
+    /// Validates every directive, so that all the invalid ones are reported at once.
+    ///
+    /// Conditions are validated along with the rule they apply to, as the engine
+    /// does not accept them on their own.
+    pub fn validate(&self) -> Vec<RuleError> {
+        // errors are keyed by directive index to report them in declaration order
+        let mut errors = Vec::new();
+        let mut conditions: Vec<(usize, &Directive)> = Vec::new();
+
+        for (index, directive) in self.directives.iter().enumerate() {
+            if directive.is(CONDITION_DIRECTIVE) {
+                conditions.push((index, directive));
+                continue;
+            }
+
+            let result = check(&directive.text);
+
+            if let Err(message) = &result {
+                errors.push((index, directive.error(message)));
+            }
+
+            if !directive.is(RULE_DIRECTIVE) {
+                errors.extend(conditions.drain(..).map(|(i, c)| (i, dangling(c))));
+                continue;
+            }
+
+            let rule = match result {
+                Ok(()) => directive.text.as_str(),
+                Err(_) => NEUTRAL_RULE,
+            };
+
+            for (i, condition) in conditions.drain(..) {
+                if let Err(message) = check(&format!("{}\n{rule}", condition.text)) {
+                    errors.push((i, condition.error(message)));
+                }
+            }
+        }
+
+        errors.extend(conditions.into_iter().map(|(i, c)| (i, dangling(c))));
+        errors.sort_by_key(|(i, _)| *i);
+        errors.into_iter().map(|(_, e)| e).collect()
+    }
+
+    /// The rule set with `%{DOCUMENT_ROOT}` replaced by `root`, for hubs whose files are not
+    /// found under the directory known to the engine.
+    pub fn with_document_root(&self, root: &Path) -> Self {
+        let root = root.to_string_lossy();
+        let root = root.trim_end_matches('/');
+
+        Self {
+            directives: self
+                .directives
+                .iter()
+                .map(|d| Directive {
+                    text: d.text.replace("%{DOCUMENT_ROOT}", root),
+                    ..d.clone()
+                })
+                .collect(),
+        }
+    }
+
+    /// Builds the rewrite engine, an empty rule set leaves requests untouched.
+    pub fn engine(&self) -> Engine {
+        let mut engine = Engine::new();
+
+        if !self.is_empty() {
+            engine
+                .add_rules(&self.to_string())
+                .expect("failed to process rules");
+        }
+
+        engine
+    }
+}
+
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for directive in &self.directives {
+            writeln!(f, "{}", directive.text)?;
+        }
+        Ok(())
+    }
+}
+
+/// The rule sets declared by each hub, empty when a hub has none.
+#[derive(Debug, Clone, Default)]
+pub struct HubRules {
+    pub _static: RuleSet,
+    pub upstream: RuleSet,
+}
+
+impl HubRules {
+    pub fn load(configuration: &Configuration) -> io::Result<Self> {
+        let hubs = configuration.hubs.as_ref();
+        let _static = hubs.and_then(|h| h._static.as_ref());
+        let upstream = hubs.and_then(|h| h.upstream.as_ref());
+
+        Ok(Self {
+            _static: RuleSet::load(
+                "static",
+                _static.and_then(|s| s.rewrite_rules.as_ref()),
+                _static.and_then(|s| s.rewrite_rules_file.as_ref()),
+            )?,
+            upstream: RuleSet::load(
+                "upstream",
+                upstream.and_then(|u| u.rewrite_rules.as_ref()),
+                upstream.and_then(|u| u.rewrite_rules_file.as_ref()),
+            )?,
+        })
+    }
+
+    /// Loads and validates the rules of every hub, failing with all the invalid directives.
+    pub fn load_validated(configuration: &Configuration) -> io::Result<Self> {
+        let rules = Self::load(configuration)?;
+        let errors = rules.validate();
+
+        if errors.is_empty() {
+            Ok(rules)
+        } else {
+            Err(report(&errors))
+        }
+    }
+
+    pub fn validate(&self) -> Vec<RuleError> {
+        let mut errors = self._static.validate();
+        errors.extend(self.upstream.validate());
+        errors
+    }
+}
+
+/// Gathers rule errors into a single error, one per line.
+pub fn report(errors: &[RuleError]) -> io::Error {
+    let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("invalid rewrite rules:\n{}", lines.join("\n")),
+    )
+}
+
+fn check(rules: &str) -> Result<(), String> {
+    Engine::new()
+        .add_rules(rules)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Whether a directive has a flag, e.g. `NC` for `[NC,L]`.
+fn has_flag(directive: &str, flag: &str) -> bool {
+    directive
+        .split_whitespace()
+        .last()
+        .and_then(|t| t.strip_prefix('[')?.strip_suffix(']'))
+        .is_some_and(|flags| {
+            flags
+                .split(',')
+                .any(|f| f.trim().eq_ignore_ascii_case(flag))
+        })
+}
+
+/// The pattern of a rule, and whether it is matched regardless of case.
+fn rule_pattern(rule: &str) -> (&str, bool) {
+    let pattern = rule.split_whitespace().nth(1).unwrap_or("^");
+    (pattern, has_flag(rule, "NC") || has_flag(rule, "nocase"))
+}
+
+/// A rule of the same pattern and case sensitivity as `rule`, rewriting to `target`.
+fn probe_rule(rule: &str, target: &str) -> String {
+    match rule_pattern(rule) {
+        (pattern, true) => format!("{RULE_DIRECTIVE} {pattern} {target} [NC,L]"),
+        (pattern, false) => format!("{RULE_DIRECTIVE} {pattern} {target} [L]"),
+    }
+}
+
+/// The patterns of the rules of a rule set, telling the paths they may apply to without running
+/// the engine.
+#[derive(Debug, Clone, Default)]
+pub struct RulePatterns(Vec<Option<Regex>>);
+
+impl RulePatterns {
+    /// Whether a rule may apply to a path relative to the remote path, whatever its conditions.
+    pub fn may_rewrite(&self, relative: &str) -> bool {
+        let path = relative.trim_start_matches('/');
+        let rooted = format!("/{path}");
+        self.0.iter().any(|pattern| {
+            pattern
+                .as_ref()
+                .is_none_or(|p| p.is_match(path) || p.is_match(&rooted))
+        })
+    }
+}
+
+fn dangling(condition: &Directive) -> RuleError {
+    condition.error(format!(
+        "{CONDITION_DIRECTIVE} is not followed by a {RULE_DIRECTIVE}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_skips_comments_and_containers() {
+        let set = RuleSet::parse(
+            "rules.conf",
+            "# SPA fallback\n<IfModule mod_rewrite.c>\n  RewriteEngine On\n\n  RewriteRule ^ /index.html\n</IfModule>\n",
+        );
+        assert_eq!(
+            set.to_string(),
+            "RewriteEngine On\nRewriteRule ^ /index.html\n"
+        );
+        assert_eq!(set.directives[0].line, 3);
+        assert_eq!(set.directives[0].column, 3);
+    }
+
+    #[test]
+    fn test_dangling_condition() {
+        let set = RuleSet::parse(
+            "rules.conf",
+            "RewriteEngine On\nRewriteCond %{REQUEST_URI} !-f\n",
+        );
+        let errors = set.validate();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(
+            errors[0].to_string(),
+            "rules.conf:2:1: RewriteCond is not followed by a RewriteRule"
+        );
+    }
+
+    #[test]
+    fn test_condition_probes() {
+        let set = RuleSet::parse(
+            "rules.conf",
+            "RewriteEngine On\nRewriteRule ^old$ /new\nRewriteCond %{HTTP_HOST} ^(www)\\.\nRewriteCond %1 =www\nRewriteRule ^(.*)$ /%1/$1 [NC,R=301]\n",
+        );
+        let probes = set.condition_probes("/matched");
+        assert_eq!(probes.len(), 2);
+        assert_eq!(probes[1].0, "rules.conf:4");
+        assert_eq!(probes[1].1, "RewriteCond %1 =www");
+        assert_eq!(
+            probes[1].2.to_string(),
+            "RewriteEngine On\nRewriteRule ^old$ /new\nRewriteCond %{HTTP_HOST} ^(www)\\.\nRewriteCond %1 =www\nRewriteRule ^(.*)$ /matched [NC,L]\n"
+        );
+    }
+
+    #[test]
+    fn test_file_rewrites() {
+        let set = RuleSet::parse(
+            "rules.conf",
+            "RewriteEngine On\nRewriteRule ^/?old/(.*)$ /new/$1 [NC]\nRewriteCond %{DOCUMENT_ROOT}%{REQUEST_URI} !-f\nRewriteCond %{DOCUMENT_ROOT}%{REQUEST_URI} !-d\nRewriteRule ^ /index.html\n",
+        );
+        let rewrites = set.file_rewrites(true);
+        assert!(rewrites.may_rewrite("/OLD/app.js"));
+        assert!(rewrites.may_rewrite("old/app.js"));
+        assert!(!rewrites.may_rewrite("/assets/app.js"));
+        // files are never found by the engine
+        assert!(set.file_rewrites(false).may_rewrite("/assets/app.js"));
+
+        let set = RuleSet::parse(
+            "rules.conf",
+            "RewriteCond %{HTTP_HOST} ^www [OR]\nRewriteCond %{REQUEST_FILENAME} !-f\nRewriteRule ^ /index.html\nRewriteRule !^/api /gone\n",
+        );
+        let rewrites = set.file_rewrites(true);
+        // an or-ed condition does not require the file to be missing
+        assert_eq!(rewrites.0.len(), 2);
+        assert!(rewrites.0[1].is_none());
+        assert!(rewrites.may_rewrite("/assets/app.js"));
+        assert!(RuleSet::default().file_rewrites(true).0.is_empty());
+    }
+
+    #[test]
+    fn test_valid_rules() {
+        let set = RuleSet::parse(
+            "rules.conf",
+            "RewriteEngine On\nRewriteCond %{DOCUMENT_ROOT}%{REQUEST_URI} !-f\nRewriteCond %{DOCUMENT_ROOT}%{REQUEST_URI} !-d\nRewriteRule ^ /index.html\n",
+        );
+        assert!(set.validate().is_empty());
+    }
+}
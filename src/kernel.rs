@@ -1,11 +1,23 @@
 use crate::{
     core::{
-        cli,
+        cli::{self, Command, ReleaseCommand},
         configuration::{self, Configuration},
     },
-    net::http_adapter::HttpAdapter,
+    net::{
+        http_adapter::HttpAdapter,
+        live_reload::{self, LiveReload},
+        releases::Releases,
+        rewrite_tester::RewriteTester,
+        static_hub::StaticHub,
+        virtual_hosts,
+    },
 };
+use actix_web::web::Data;
 use clap::Parser;
+use std::path::PathBuf;
+
+/// How many times a restarted server tries to bind again its address.
+const BIND_ATTEMPTS: u32 = 50;
 
 /// boot up the application kernel
 /// ``` rust
@@ -14,23 +26,200 @@ use clap::Parser;
 pub async fn boot() -> std::io::Result<Kernel> {
     let cli = cli::Cli::parse();
 
-    let conf = configuration::load_configuration(
-        &cli.configuration_path.unwrap_or("conf.yaml".to_string()),
-    )
-    .await
-    .unwrap();
+    let configuration_path = cli.configuration_path.unwrap_or("conf.yaml".to_string());
+    let conf = configuration::load_configuration(&configuration_path)
+        .await
+        .unwrap();
 
-    Ok(Kernel::new(conf))
+    Ok(Kernel::new(conf)
+        .with_check(cli.check)
+        .with_dev(cli.dev, &configuration_path)
+        .with_command(cli.command))
 }
 
 /// The application kernel, responsible for managing the application's lifecycle and providing access to its core components.
 pub struct Kernel {
     configuration: Configuration,
+    check: bool,
+    /// The configuration file watched in development mode.
+    dev: Option<PathBuf>,
+    command: Option<Command>,
 }
 
 impl Kernel {
     pub fn new(configuration: Configuration) -> Self {
-        Self { configuration }
+        Self {
+            configuration,
+            check: false,
+            dev: None,
+            command: None,
+        }
+    }
+
+    /// Only validate the configuration instead of serving it.
+    pub fn with_check(mut self, check: bool) -> Self {
+        self.check = check;
+        self
+    }
+
+    /// Serve in development mode, restarting when the configuration file changes.
+    pub fn with_dev(mut self, dev: bool, configuration_path: &str) -> Self {
+        self.dev = dev.then(|| PathBuf::from(configuration_path));
+        self
+    }
+
+    /// Run a CLI subcommand instead of serving the configuration.
+    pub fn with_command(mut self, command: Option<Command>) -> Self {
+        self.command = command;
+        self
+    }
+
+    pub fn command(&self) -> Option<&Command> {
+        self.command.as_ref()
+    }
+
+    pub fn check_only(&self) -> bool {
+        self.check
+    }
+
+    pub fn dev(&self) -> bool {
+        self.dev.is_some()
+    }
+
+    /// Validates the rewrite rules of every hub, the redirects, the compression policies, the
+    /// cache rules, the error pages, the access policy, the static hub options, the releases, the
+    /// sites and the admin API, reporting all the invalid ones.
+    pub fn check(&self) -> std::io::Result<()> {
+        let errors = virtual_hosts::validate(&self.configuration);
+
+        if errors.is_empty() {
+            println!("configuration is valid");
+            return Ok(());
+        }
+
+        for error in &errors {
+            eprintln!("{error}");
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} invalid option(s)", errors.len()),
+        ))
+    }
+
+    /// Prints how each URL goes through the rewrite rules and which hub serves it, on the site
+    /// of `site` or of the host of each URL.
+    pub async fn test_rewrite(&self, urls: &[String], site: Option<&str>) -> std::io::Result<()> {
+        let tester = RewriteTester::new(&self.configuration)?.with_host(site);
+
+        for url in urls {
+            print!("{}", tester.trace(url).await);
+        }
+
+        Ok(())
+    }
+
+    /// Lists the releases of the static hub of the site of `site`, switches to one or rolls back
+    /// to the previous one.
+    pub fn release(&self, command: &ReleaseCommand, site: Option<&str>) -> std::io::Result<()> {
+        let configuration = match site {
+            Some(host) => virtual_hosts::site_configuration(&self.configuration, host),
+            None => self.configuration.clone(),
+        };
+        let static_conf = configuration.hubs.as_ref().and_then(|h| h._static.as_ref());
+        let Some(releases) = Releases::new(static_conf) else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "the static hub has no releases directory",
+            ));
+        };
+
+        match command {
+            ReleaseCommand::List => {
+                let (current, previous) = (releases.current(), releases.previous());
+                for release in releases.list()? {
+                    let marker = match Some(&release) {
+                        r if r == current.as_ref() => "* ",
+                        r if r == previous.as_ref() => "- ",
+                        _ => "  ",
+                    };
+                    println!("{marker}{release}");
+                }
+            }
+            ReleaseCommand::Switch { release } => {
+                releases.switch(release)?;
+                println!("serving release '{release}'");
+            }
+            ReleaseCommand::Rollback => {
+                let release = releases.rollback()?;
+                println!("rolled back to release '{release}'");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Serves without caching, reloading the browsers when the static files change and restarting
+    /// the server with the new configuration when the configuration file changes.
+    pub async fn serve_dev(&self) -> std::io::Result<()> {
+        let Some(configuration_path) = self.dev.as_ref() else {
+            return self.setup_http_adapter().run().await;
+        };
+        let live_reload = Data::new(LiveReload::default());
+        let mut configuration = self.configuration.clone();
+
+        let mut restarts = 0;
+
+        loop {
+            let adapter = HttpAdapter::new(&configuration).with_live_reload(live_reload.clone());
+            let server = match adapter.server() {
+                // the stopped server releases its address asynchronously
+                Err(e) if e.kind() == std::io::ErrorKind::AddrInUse && restarts > 0 => {
+                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                    restarts -= 1;
+                    continue;
+                }
+                server => server?,
+            };
+            let handle = server.handle();
+            // the static hubs of every site
+            let mut static_paths = Vec::new();
+            let sites = configuration
+                .sites
+                .iter()
+                .flatten()
+                .map(|s| configuration.site(s));
+            for conf in std::iter::once(configuration.clone()).chain(sites) {
+                let static_conf = conf.hubs.as_ref().and_then(|h| h._static.as_ref());
+                static_paths.push(PathBuf::from(StaticHub::new(static_conf)?.path));
+            }
+            let watcher = live_reload::watch(
+                static_paths,
+                configuration_path.clone(),
+                live_reload.clone(),
+            );
+
+            tokio::pin!(server);
+            tokio::select! {
+                // stopped by a signal
+                res = &mut server => return res,
+                () = watcher => {}
+            }
+            // the browsers reload once reconnected to the restarted server
+            // the server processes its own stop command, so both are driven together
+            let ((), stopped) = tokio::join!(handle.stop(false), server);
+            stopped?;
+
+            let path = configuration_path.to_string_lossy();
+            let reloaded = match configuration::load_configuration(&path).await {
+                Ok(reloaded) => Kernel::new(reloaded.clone()).check().map(|_| reloaded),
+                Err(e) => Err(e),
+            };
+            restarts = BIND_ATTEMPTS;
+            match reloaded {
+                Ok(reloaded) => configuration = reloaded,
+                Err(e) => eprintln!("keeping the previous configuration, '{path}' is invalid: {e}"),
+            }
+        }
     }
 
     pub fn setup_http_adapter(&self) -> HttpAdapter {
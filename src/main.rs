@@ -8,6 +8,18 @@ async fn main() -> std::io::Result<()> {
     let krn = kernel::boot().await.unwrap();
     // let logger = krn.setup_logger();
     // logger.splash();
+    if krn.check_only() {
+        return krn.check();
+    }
+    if let Some(core::cli::Command::TestRewrite { urls, site }) = krn.command() {
+        return krn.test_rewrite(urls, site.as_deref()).await;
+    }
+    if let Some(core::cli::Command::Release { command, site }) = krn.command() {
+        return krn.release(command, site.as_deref());
+    }
+    if krn.dev() {
+        return krn.serve_dev().await;
+    }
     let adapter = krn.setup_http_adapter();
     adapter.run().await
 }
@@ -1,5 +1,48 @@
+pub mod access_middleware;
+pub mod admin;
+pub mod archive;
+pub mod cache_middleware;
+pub mod clean_urls_middleware;
+pub mod compression_middleware;
+pub mod content_etag_middleware;
+pub mod content_type_middleware;
+pub mod directory_listing;
+pub mod embedded_assets;
+pub mod env_injection_middleware;
+pub mod env_substitution_middleware;
 pub mod environment_middleware;
+pub mod error_pages_middleware;
 pub mod headers_middleware;
+pub mod html_document;
 pub mod http_adapter;
+pub mod image_middleware;
+pub mod integrity_middleware;
+pub mod live_reload;
+pub mod locale_middleware;
+pub mod precompressed_middleware;
+pub mod redirect_middleware;
+pub mod releases;
 pub mod reverse_proxy_middleware;
+pub mod rewrite_tester;
+pub mod static_hub;
+pub mod storage;
+pub mod virtual_hosts;
 pub const DEFAULT_CONF_REMOTE_PATH: &str = "/env";
+
+/// Escapes text inserted in generated HTML pages.
+pub fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Whether the client asks for JSON rather than HTML.
+pub fn prefers_json(headers: &actix_web::http::header::HeaderMap) -> bool {
+    headers
+        .get(actix_web::http::header::ACCEPT)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| h.to_ascii_lowercase())
+        .is_some_and(|h| h.contains("json") && !h.contains("text/html"))
+}
@@ -0,0 +1,359 @@
+use std::{
+    future::{Ready, ready},
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::StatusCode,
+    web::Data,
+};
+use regex::Regex;
+
+use crate::{
+    core::configuration::{Configuration, DotfilesPolicy, SymlinkPolicy},
+    net::{
+        cache_middleware::glob_regex,
+        static_hub::{StaticHub, normalize},
+    },
+};
+
+/// A glob of the access policy.
+#[derive(Debug, Clone)]
+struct PathPattern {
+    regex: Regex,
+    /// Matched against the whole path when the glob has a `/`, against every segment otherwise.
+    full_path: bool,
+    /// A trailing slash stands for a directory and everything beneath it.
+    directory: bool,
+}
+
+impl PathPattern {
+    fn new(glob: &str) -> Result<Self, String> {
+        let directory = glob.ends_with('/');
+        let pattern = if directory {
+            format!("{glob}**")
+        } else {
+            glob.to_string()
+        };
+        let regex = glob_regex(&pattern.to_lowercase())
+            .map_err(|e| format!("invalid access glob '{glob}': {e}"))?;
+
+        Ok(Self {
+            regex,
+            full_path: glob.contains('/'),
+            directory,
+        })
+    }
+
+    /// Whether the pattern matches a normalized path, case-insensitively.
+    fn matches(&self, path: &str) -> bool {
+        let path = path.to_lowercase();
+
+        if self.directory {
+            self.regex.is_match(&path) || self.regex.is_match(&format!("{path}/"))
+        } else if self.full_path {
+            self.regex.is_match(&path)
+        } else {
+            path.split('/').any(|segment| self.regex.is_match(segment))
+        }
+    }
+}
+
+/// The paths of the static hub that are served, and how symlinks are followed.
+#[derive(Debug, Clone)]
+pub struct AccessPolicy {
+    deny_dotfiles: bool,
+    deny: Vec<PathPattern>,
+    allow: Vec<PathPattern>,
+    symlinks: SymlinkPolicy,
+    /// Answered to denied requests, 404 so that their existence is not disclosed.
+    status: StatusCode,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        Self {
+            deny_dotfiles: true,
+            deny: Vec::new(),
+            allow: Vec::new(),
+            symlinks: SymlinkPolicy::Follow,
+            status: StatusCode::NOT_FOUND,
+        }
+    }
+}
+
+impl AccessPolicy {
+    pub fn new(configuration: &Configuration) -> std::io::Result<Self> {
+        let conf = configuration
+            .hubs
+            .as_ref()
+            .and_then(|h| h._static.as_ref())
+            .and_then(|s| s.access.as_ref());
+        let Some(conf) = conf else {
+            return Ok(Self::default());
+        };
+
+        let mut errors = Vec::new();
+        let mut compile = |globs: Option<&Vec<String>>| -> Vec<PathPattern> {
+            globs
+                .into_iter()
+                .flatten()
+                .filter_map(|g| PathPattern::new(g).map_err(|e| errors.push(e)).ok())
+                .collect()
+        };
+        let deny = compile(conf.deny.as_ref());
+        let allow = compile(conf.allow.as_ref());
+
+        let status = match conf.status {
+            None | Some(404) => StatusCode::NOT_FOUND,
+            Some(403) => StatusCode::FORBIDDEN,
+            Some(status) => {
+                errors.push(format!(
+                    "invalid access status {status}, expected 403 or 404"
+                ));
+                StatusCode::NOT_FOUND
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid access policy:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Self {
+            deny_dotfiles: conf.dotfiles.unwrap_or(DotfilesPolicy::Deny) == DotfilesPolicy::Deny,
+            deny,
+            allow,
+            symlinks: conf.symlinks.unwrap_or(SymlinkPolicy::Follow),
+            status,
+        })
+    }
+
+    /// Whether a normalized path, relative to the hub root, may be served.
+    pub fn permits(&self, path: &str) -> bool {
+        let denied = (self.deny_dotfiles && path.split('/').any(|s| s.starts_with('.')))
+            || self.deny.iter().any(|p| p.matches(path));
+
+        !denied || self.allow.iter().any(|p| p.matches(path))
+    }
+}
+
+/// Whether the symlinks met along a path under `root`, or to the index document served for a
+/// directory, are followed by the policy.
+fn symlinks_permitted(
+    root: &Path,
+    relative: &str,
+    index: &[String],
+    policy: SymlinkPolicy,
+) -> bool {
+    if policy == SymlinkPolicy::Follow {
+        return true;
+    }
+    // a root that cannot be resolved cannot vouch for any link
+    let Ok(canonical_root) = std::fs::canonicalize(root) else {
+        return false;
+    };
+
+    let mut path = PathBuf::from(root);
+    let segments: Vec<&str> = relative.split('/').filter(|s| !s.is_empty()).collect();
+    let full_path = root.join(segments.join("/"));
+    let index = index
+        .iter()
+        .find(|i| full_path.join(i).is_file())
+        .filter(|_| full_path.is_dir());
+
+    for segment in segments.into_iter().chain(index.map(String::as_str)) {
+        path.push(segment);
+
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            // nothing is served from a missing path
+            return true;
+        };
+        if !metadata.file_type().is_symlink() {
+            continue;
+        }
+
+        let within_root =
+            std::fs::canonicalize(&path).is_ok_and(|t| t.starts_with(&canonical_root));
+        if policy == SymlinkPolicy::Deny || !within_root {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Answers the requests for denied paths of the static hub like missing files.
+pub struct AccessMiddleware;
+
+pub struct AccessMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for AccessMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = AccessMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AccessMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for AccessMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let Some(policy) = req.app_data::<Data<AccessPolicy>>().cloned() else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        // checked once rewritten, on the path actually served
+        let path = normalize(req.match_info().unprocessed());
+        let root = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|h| !h.embedded && h.serves_local_directory())
+            .map(|h| (PathBuf::from(&h.path), h.index.clone()));
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let permitted = match path {
+                Some(path) if policy.permits(&path) => match root {
+                    Some((root, index)) if policy.symlinks != SymlinkPolicy::Follow => {
+                        let symlinks = policy.symlinks;
+                        tokio::task::spawn_blocking(move || {
+                            symlinks_permitted(&root, &path, &index, symlinks)
+                        })
+                        .await
+                        .unwrap_or(false)
+                    }
+                    _ => true,
+                },
+                // paths escaping the root are left to the hub, which never serves them
+                None => true,
+                Some(_) => false,
+            };
+
+            if !permitted {
+                let (req, _pl) = req.into_parts();
+                let res = HttpResponse::build(policy.status).finish();
+                return Ok(ServiceResponse::new(req, res));
+            }
+
+            service.call(req).await.map(|res| res.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_policy() {
+        let conf: Configuration = serde_yaml::from_str(
+            r#"
+hubs:
+  static:
+    access:
+      deny: ["*.sql", "/private/"]
+      allow: ["/.well-known/"]
+"#,
+        )
+        .unwrap();
+        let policy = AccessPolicy::new(&conf).unwrap();
+
+        assert!(policy.permits("index.html"));
+        assert!(!policy.permits(".env"));
+        assert!(!policy.permits(".git/config"));
+        assert!(!policy.permits("dumps/backup.SQL"));
+        assert!(!policy.permits("private"));
+        assert!(!policy.permits("private/keys.txt"));
+        assert!(policy.permits("private-notes.txt"));
+        assert!(policy.permits(".well-known/security.txt"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_permitted() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let root = dir.join("root");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(dir.join("outside.txt"), "secret").unwrap();
+        std::fs::write(root.join("inside.txt"), "public").unwrap();
+        for (link, target) in [
+            ("out.txt", dir.join("outside.txt")),
+            ("in.txt", root.join("inside.txt")),
+        ] {
+            std::os::unix::fs::symlink(target, root.join(link)).unwrap();
+        }
+
+        assert!(symlinks_permitted(
+            &root,
+            "out.txt",
+            &[],
+            SymlinkPolicy::Follow
+        ));
+        assert!(!symlinks_permitted(
+            &root,
+            "out.txt",
+            &[],
+            SymlinkPolicy::WithinRoot
+        ));
+        assert!(symlinks_permitted(
+            &root,
+            "in.txt",
+            &[],
+            SymlinkPolicy::WithinRoot
+        ));
+        assert!(!symlinks_permitted(
+            &root,
+            "in.txt",
+            &[],
+            SymlinkPolicy::Deny
+        ));
+        assert!(symlinks_permitted(
+            &root,
+            "inside.txt",
+            &[],
+            SymlinkPolicy::Deny
+        ));
+        assert!(!symlinks_permitted(
+            &dir.join("missing"),
+            "inside.txt",
+            &[],
+            SymlinkPolicy::WithinRoot
+        ));
+    }
+}
@@ -0,0 +1,194 @@
+use std::io;
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    http::header,
+    web::{self, Data},
+};
+use serde_json::json;
+
+use crate::{
+    core::configuration::Configuration,
+    net::{http_adapter::hub_scope, releases::Releases},
+};
+
+const DEFAULT_REMOTE_PATH: &str = "/__sthub/admin";
+/// Holds the token when the configuration does not.
+const TOKEN_VARIABLE: &str = "STHUB_ADMIN_TOKEN";
+
+/// The administration API, requiring a bearer token.
+#[derive(Debug, Clone)]
+pub struct Admin {
+    pub remote_path: String,
+    token: String,
+}
+
+impl Admin {
+    /// `None` when the API is disabled.
+    pub fn new(configuration: &Configuration) -> io::Result<Option<Self>> {
+        let Some(conf) = configuration.admin.as_ref() else {
+            return Ok(None);
+        };
+
+        let token = conf
+            .token
+            .clone()
+            .or_else(|| std::env::var(TOKEN_VARIABLE).ok())
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid admin API:\nno token, set 'token' or {TOKEN_VARIABLE}"),
+                )
+            })?;
+
+        Ok(Some(Self {
+            remote_path: conf
+                .remote_path
+                .clone()
+                .unwrap_or_else(|| DEFAULT_REMOTE_PATH.to_string()),
+            token,
+        }))
+    }
+
+    fn authorized(&self, req: &HttpRequest) -> bool {
+        let Some(token) = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+
+        // compared in constant time, not to tell how much of the token matched
+        token.len() == self.token.len()
+            && token
+                .bytes()
+                .zip(self.token.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+/// Fails when the admin API is enabled without token.
+pub fn check(configuration: &Configuration) -> io::Result<()> {
+    Admin::new(configuration).map(|_| ())
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized()
+        .insert_header((header::WWW_AUTHENTICATE, "Bearer"))
+        .json(json!({ "error": "missing or invalid token" }))
+}
+
+fn error_response(e: io::Error) -> HttpResponse {
+    let body = json!({ "error": e.to_string() });
+    match e.kind() {
+        io::ErrorKind::NotFound => HttpResponse::NotFound().json(body),
+        io::ErrorKind::InvalidInput => HttpResponse::BadRequest().json(body),
+        _ => HttpResponse::InternalServerError().json(body),
+    }
+}
+
+/// Runs a release operation off the workers, answering with the releases once done.
+async fn releases_response<T: Send + 'static>(
+    req: HttpRequest,
+    admin: Data<Admin>,
+    releases: Data<Option<Releases>>,
+    operation: impl FnOnce(&Releases) -> io::Result<T> + Send + 'static,
+) -> HttpResponse {
+    if !admin.authorized(&req) {
+        return unauthorized();
+    }
+    // the releases of the site the host of the request is routed to
+    let Some(releases) = releases.get_ref().clone() else {
+        return HttpResponse::NotFound().json(json!({ "error": "the static hub has no releases" }));
+    };
+
+    let result = web::block(move || {
+        operation(&releases)?;
+        Ok::<_, io::Error>(json!({
+            "current": releases.current(),
+            "previous": releases.previous(),
+            "releases": releases.list()?,
+        }))
+    })
+    .await
+    .map_err(io::Error::other)
+    .and_then(|r| r);
+
+    match result {
+        Ok(body) => HttpResponse::Ok().json(body),
+        Err(e) => error_response(e),
+    }
+}
+
+async fn list(
+    req: HttpRequest,
+    admin: Data<Admin>,
+    releases: Data<Option<Releases>>,
+) -> HttpResponse {
+    releases_response(req, admin, releases, |_| Ok(())).await
+}
+
+async fn switch(
+    req: HttpRequest,
+    admin: Data<Admin>,
+    releases: Data<Option<Releases>>,
+    release: web::Path<String>,
+) -> HttpResponse {
+    let release = release.into_inner();
+    releases_response(req, admin, releases, move |r| r.switch(&release)).await
+}
+
+async fn rollback(
+    req: HttpRequest,
+    admin: Data<Admin>,
+    releases: Data<Option<Releases>>,
+) -> HttpResponse {
+    releases_response(req, admin, releases, Releases::rollback).await
+}
+
+/// Mounts the admin API, switching the releases of the static hub of the site of each request.
+pub fn config(cfg: &mut web::ServiceConfig, admin: &Admin) {
+    cfg.service(
+        hub_scope(&admin.remote_path)
+            .app_data(Data::new(admin.clone()))
+            .route("/releases", web::get().to(list))
+            .route("/releases/rollback", web::post().to(rollback))
+            .route("/releases/{release}/activate", web::post().to(switch)),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, http::StatusCode, test as actix_test};
+
+    #[actix_web::test]
+    async fn test_admin_token() {
+        let configuration: Configuration = serde_yaml::from_str("admin: {token: secret}").unwrap();
+        let admin = Admin::new(&configuration).unwrap().unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(Data::new(None::<Releases>))
+                .configure(|cfg| config(cfg, &admin)),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/__sthub/admin/releases")
+            .insert_header((header::AUTHORIZATION, "Bearer secreT"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/__sthub/admin/releases")
+            .insert_header((header::AUTHORIZATION, "Bearer secret"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
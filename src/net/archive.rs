@@ -0,0 +1,214 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use tempfile::TempDir;
+
+use crate::net::storage::{Metadata, Storage, StorageEntry, StorageFuture, memory::MemoryStorage};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveKind {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl ArchiveKind {
+    fn of(path: &str) -> Option<Self> {
+        let path = path.to_ascii_lowercase();
+
+        if path.ends_with(".tar.gz") || path.ends_with(".tgz") {
+            Some(ArchiveKind::TarGz)
+        } else if path.ends_with(".tar") {
+            Some(ArchiveKind::Tar)
+        } else if path.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// The files of a `.tar`, `.tar.gz` or `.zip` archive, indexed and loaded in memory.
+#[derive(Debug)]
+pub struct Archive {
+    files: MemoryStorage,
+    /// A tree mirroring the archive layout with sparse files, for the `-f`, `-d` and `-s`
+    /// rewrite conditions that look for files on disk. Owned by each instance and removed
+    /// with it.
+    document_root: TempDir,
+}
+
+impl Archive {
+    /// Whether the static hub path points at an archive rather than a directory.
+    pub fn is_archive(path: &str) -> bool {
+        ArchiveKind::of(path).is_some()
+    }
+
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let kind = ArchiveKind::of(&path.to_string_lossy()).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "'{}' is not a .tar, .tar.gz or .zip archive",
+                    path.display()
+                ),
+            )
+        })?;
+
+        let file = File::open(path).map_err(|e| {
+            io::Error::new(
+                e.kind(),
+                format!("failed to open archive '{}': {e}", path.display()),
+            )
+        })?;
+
+        let mut files = MemoryStorage::default();
+        match kind {
+            ArchiveKind::Tar => read_tar(file, &mut files)?,
+            ArchiveKind::TarGz => read_tar(flate2::read::GzDecoder::new(file), &mut files)?,
+            ArchiveKind::Zip => read_zip(file, &mut files)?,
+        }
+
+        let document_root = tempfile::Builder::new()
+            .prefix("sthub-archive-")
+            .tempdir()?;
+        for directory in files.directories() {
+            std::fs::create_dir_all(document_root.path().join(directory))?;
+        }
+        for (name, len) in files.files() {
+            File::create(document_root.path().join(name))?.set_len(len)?;
+        }
+
+        Ok(Self {
+            files,
+            document_root,
+        })
+    }
+}
+
+impl Storage for Archive {
+    fn metadata<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Option<Metadata>> {
+        self.files.metadata(path)
+    }
+
+    fn read<'a>(&'a self, path: &'a str, range: Option<(u64, u64)>) -> StorageFuture<'a, Bytes> {
+        self.files.read(path, range)
+    }
+
+    fn list<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Vec<StorageEntry>> {
+        self.files.list(path)
+    }
+
+    fn document_root(&self) -> Option<&Path> {
+        Some(self.document_root.path())
+    }
+}
+
+/// The entry name relative to the archive root, `None` when it would escape it.
+fn entry_name(name: &str) -> Option<String> {
+    let segments: Vec<&str> = name
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+
+    (!segments.is_empty() && !segments.contains(&"..")).then(|| segments.join("/"))
+}
+
+fn read_tar(reader: impl Read, files: &mut MemoryStorage) -> io::Result<()> {
+    let mut archive = tar::Archive::new(reader);
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let Some(name) = entry_name(&entry.path()?.to_string_lossy()) else {
+            continue;
+        };
+
+        let kind = entry.header().entry_type();
+        if kind.is_dir() {
+            files.insert_directory(&name);
+        } else if kind.is_file() {
+            let modified = entry
+                .header()
+                .mtime()
+                .ok()
+                .map(|m| UNIX_EPOCH + Duration::from_secs(m));
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data)?;
+            files.insert(&name, Bytes::from(data), modified);
+        }
+    }
+
+    Ok(())
+}
+
+fn read_zip(file: File, files: &mut MemoryStorage) -> io::Result<()> {
+    let mut archive = zip::ZipArchive::new(file).map_err(io::Error::other)?;
+
+    for index in 0..archive.len() {
+        let mut entry = archive.by_index(index).map_err(io::Error::other)?;
+        let Some(name) = entry_name(entry.name()) else {
+            continue;
+        };
+
+        if entry.is_dir() {
+            files.insert_directory(&name);
+        } else {
+            let mut data = Vec::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut data)?;
+            files.insert(&name, Bytes::from(data), None);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_open_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.tar");
+
+        let mut builder = tar::Builder::new(File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(13);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "./assets/index.html", &b"<html></html>"[..])
+            .unwrap();
+        builder.finish().unwrap();
+        drop(builder);
+
+        let archive = Archive::open(&path).unwrap();
+        let metadata = archive.metadata("assets/index.html").await.unwrap();
+        assert_eq!(metadata.map(|m| m.size), Some(13));
+        assert!(archive.metadata("assets").await.unwrap().unwrap().is_dir());
+
+        let document_root = Storage::document_root(&archive).unwrap().to_path_buf();
+        assert!(document_root.join("assets/index.html").is_file());
+        assert!(document_root.join("assets").is_dir());
+
+        // every instance has its own tree, removed with it
+        let other = Archive::open(&path).unwrap();
+        assert_ne!(
+            Storage::document_root(&other),
+            Some(document_root.as_path())
+        );
+        drop(archive);
+        assert!(!document_root.exists());
+        assert!(
+            Storage::document_root(&other)
+                .unwrap()
+                .join("assets/index.html")
+                .is_file()
+        );
+    }
+}
@@ -0,0 +1,495 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    str::FromStr,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use actix_web::{
+    HttpMessage,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        StatusCode,
+        header::{self, HeaderName, HeaderValue, HttpDate},
+    },
+    web::Data,
+};
+use regex::Regex;
+
+use crate::{
+    core::configuration::{CacheRuleConfiguration, CacheRuleKind, CacheRulesMode, Configuration},
+    net::static_hub::StaticHub,
+};
+
+/// Placeholder of the glob patterns matching a build fingerprint, e.g. `app.[hash].js`.
+const HASH_PLACEHOLDER: &str = "[hash]";
+const HASH_CHARACTERS: &str = "[0-9A-Za-z_-]";
+const HASH_MIN_LENGTH: usize = 6;
+
+/// Matches a build fingerprint: hex digits, or characters with at least one digit, so that words
+/// such as `bundle` are not taken for one.
+fn hash_pattern() -> String {
+    let c = HASH_CHARACTERS;
+    // a digit preceded by `i` characters or more and followed by enough for the minimum length
+    let with_digit: Vec<String> = (0..HASH_MIN_LENGTH)
+        .map(|i| format!("{c}{{{i},}}[0-9]{c}{{{},}}", HASH_MIN_LENGTH - 1 - i))
+        .collect();
+    format!(
+        "(?:[0-9A-Fa-f]{{{HASH_MIN_LENGTH},}}|{})",
+        with_digit.join("|")
+    )
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    /// Matched against the file name, or the whole path when the pattern has a `/`.
+    Glob {
+        regex: Regex,
+        full_path: bool,
+    },
+    Regex(Regex),
+    Extension(String),
+}
+
+/// A cache rule of the static hub compiled from its configuration.
+#[derive(Debug, Clone)]
+pub struct CacheRule {
+    matcher: Matcher,
+    /// The `Expires` delay, computed when the response is sent.
+    expires: Option<Duration>,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl CacheRule {
+    pub fn new(conf: &CacheRuleConfiguration) -> Result<Self, String> {
+        let matcher = match conf.kind.unwrap_or(CacheRuleKind::Glob) {
+            CacheRuleKind::Glob => Matcher::Glob {
+                regex: glob_regex(&conf.pattern)
+                    .map_err(|e| format!("invalid cache rule glob '{}': {e}", conf.pattern))?,
+                full_path: conf.pattern.contains('/'),
+            },
+            CacheRuleKind::Regex => Matcher::Regex(
+                Regex::new(&conf.pattern)
+                    .map_err(|e| format!("invalid cache rule pattern '{}': {e}", conf.pattern))?,
+            ),
+            CacheRuleKind::Extension => {
+                Matcher::Extension(conf.pattern.trim_start_matches('.').to_ascii_lowercase())
+            }
+        };
+
+        let mut headers = Vec::new();
+        if let Some(cache_control) = conf.cache_control.as_ref() {
+            headers.push((header::CACHE_CONTROL.to_string(), cache_control.clone()));
+        }
+        // sorted so that the headers are applied in a stable order
+        let mut additional: Vec<_> = conf
+            .headers
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        additional.sort();
+        headers.extend(additional);
+
+        let headers = headers
+            .into_iter()
+            .map(|(key, value)| {
+                let name = HeaderName::from_str(&key).map_err(|_| {
+                    format!(
+                        "invalid header name '{key}' in cache rule '{}'",
+                        conf.pattern
+                    )
+                })?;
+                let value = HeaderValue::from_str(&value).map_err(|_| {
+                    format!(
+                        "invalid value for header '{key}' in cache rule '{}'",
+                        conf.pattern
+                    )
+                })?;
+                Ok((name, value))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            matcher,
+            expires: conf.expires.map(Duration::from_secs),
+            headers,
+        })
+    }
+
+    /// Whether the rule applies to a path relative to the hub remote path.
+    pub fn matches(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+
+        match &self.matcher {
+            Matcher::Glob { regex, full_path } if *full_path => {
+                regex.is_match(path.trim_start_matches('/'))
+            }
+            Matcher::Glob { regex, .. } => regex.is_match(file_name),
+            Matcher::Regex(regex) => regex.is_match(path),
+            Matcher::Extension(extension) => file_name
+                .rsplit_once('.')
+                .is_some_and(|(_, e)| e.eq_ignore_ascii_case(extension)),
+        }
+    }
+
+    fn headers(&self) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = self.headers.clone();
+
+        if let Some(expires) = self.expires {
+            let date = HttpDate::from(SystemTime::now() + expires);
+            // an HTTP date is always a valid header value
+            headers.push((
+                header::EXPIRES,
+                HeaderValue::from_str(&date.to_string()).unwrap(),
+            ));
+        }
+
+        headers
+    }
+}
+
+/// Translates a glob into an anchored regex: `*` matches within a segment, `**` across segments,
+/// `?` a single character and `[hash]` a build fingerprint.
+pub fn glob_regex(glob: &str) -> Result<Regex, regex::Error> {
+    let glob = glob.trim_start_matches('/');
+    let mut pattern = String::from("^");
+    let mut rest = glob;
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix(HASH_PLACEHOLDER) {
+            pattern.push_str(&hash_pattern());
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("**") {
+            pattern.push_str(".*");
+            rest = after;
+            continue;
+        }
+
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    pattern.push('$');
+    Regex::new(&pattern)
+}
+
+/// The cache rules of the static hub, evaluated in declaration order.
+#[derive(Debug, Clone, Default)]
+pub struct CacheRules {
+    rules: Vec<CacheRule>,
+    mode: Option<CacheRulesMode>,
+}
+
+impl CacheRules {
+    pub fn new(configuration: &Configuration) -> std::io::Result<Self> {
+        let hub = configuration.hubs.as_ref().and_then(|h| h._static.as_ref());
+
+        let (rules, errors): (Vec<_>, Vec<_>) = hub
+            .and_then(|h| h.cache_rules.as_ref())
+            .into_iter()
+            .flatten()
+            .map(CacheRule::new)
+            .partition(Result::is_ok);
+
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.into_iter().filter_map(Result::err).collect();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid cache rules:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Self {
+            rules: rules.into_iter().filter_map(Result::ok).collect(),
+            mode: hub.and_then(|h| h.cache_rules_mode),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// The headers of the rules matching a path relative to the hub remote path: those of the
+    /// first match, or of every match in merge mode with the later rules taking precedence.
+    pub fn resolve(&self, path: &str) -> Vec<(HeaderName, HeaderValue)> {
+        let mut matching = self.rules.iter().filter(|r| r.matches(path));
+
+        match self.mode.unwrap_or(CacheRulesMode::First) {
+            CacheRulesMode::First => matching.next().map(CacheRule::headers).unwrap_or_default(),
+            CacheRulesMode::Merge => {
+                let mut headers: HashMap<HeaderName, HeaderValue> = HashMap::new();
+                for rule in matching {
+                    headers.extend(rule.headers());
+                }
+                headers.into_iter().collect()
+            }
+        }
+    }
+}
+
+/// The path of the file served by the static hub, relative to its root, once rewritten and
+/// resolved to an index or fallback document. Kept in the request extensions for the
+/// `CacheMiddleware`.
+#[derive(Debug, Clone)]
+pub struct ServedPath(pub String);
+
+/// Records the file served for the requests of the static hub, for the cache rules.
+pub struct ServedPathMiddleware;
+
+pub struct ServedPathMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ServedPathMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ServedPathMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ServedPathMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ServedPathMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        // only looked up when there are rules to match
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|_| {
+                req.app_data::<Data<CacheRules>>()
+                    .is_some_and(|r| !r.is_empty())
+            })
+            .cloned();
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            if let Some(path) = hub.served_path(req.match_info().unprocessed()).await {
+                req.extensions_mut().insert(ServedPath(format!("/{path}")));
+            }
+            service.call(req).await.map(|res| res.map_into_boxed_body())
+        })
+    }
+}
+
+/// Applies the cache rules to the successful responses of the static hub, after the hub headers.
+pub struct CacheMiddleware;
+
+pub struct CacheMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CacheMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = CacheMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CacheMiddlewareService { service }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for CacheMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let rules = req.app_data::<Data<CacheRules>>().cloned();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?.map_into_boxed_body();
+
+            // errors and redirects must not be cached like the files they stand for
+            let status = res.status();
+            if !status.is_success() && status != StatusCode::NOT_MODIFIED {
+                return Ok(res);
+            }
+
+            // only set by the static hub, for the file it served
+            let path = res.request().extensions().get::<ServedPath>().cloned();
+            let headers = rules
+                .zip(path)
+                .map(|(rules, path)| rules.resolve(&path.0))
+                .unwrap_or_default();
+            for (name, value) in headers {
+                res.headers_mut().insert(name, value);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, kind: CacheRuleKind, cache_control: &str) -> CacheRuleConfiguration {
+        CacheRuleConfiguration {
+            pattern: pattern.to_string(),
+            kind: Some(kind),
+            cache_control: Some(cache_control.to_string()),
+            expires: None,
+            headers: None,
+        }
+    }
+
+    #[test]
+    fn test_cache_rule_kinds() {
+        let glob = CacheRule::new(&rule("*.[hash].js", CacheRuleKind::Glob, "immutable")).unwrap();
+        assert!(glob.matches("/assets/app.3f2a9c1b.js"));
+        assert!(!glob.matches("/assets/app.js"));
+        assert!(glob.matches("/assets/app.Bp4xTQ3k.js"));
+        assert!(glob.matches("/assets/chunk.deadbeef.js"));
+        // words are not fingerprints
+        assert!(!glob.matches("/assets/bootstrap.bundle.js"));
+        assert!(!glob.matches("/assets/app.v2.js"));
+
+        let nested = CacheRule::new(&rule("assets/**", CacheRuleKind::Glob, "public")).unwrap();
+        assert!(nested.matches("/assets/img/logo.png"));
+        assert!(!nested.matches("/index.html"));
+
+        let regex = CacheRule::new(&rule("^/[^.]*$", CacheRuleKind::Regex, "no-cache")).unwrap();
+        assert!(regex.matches("/users/42"));
+        assert!(!regex.matches("/app.js"));
+
+        let extension =
+            CacheRule::new(&rule(".WOFF2", CacheRuleKind::Extension, "public")).unwrap();
+        assert!(extension.matches("/fonts/inter.woff2"));
+
+        assert!(CacheRule::new(&rule("(", CacheRuleKind::Regex, "public")).is_err());
+        assert!(CacheRule::new(&rule("*", CacheRuleKind::Glob, "bad\nvalue")).is_err());
+    }
+
+    #[test]
+    fn test_cache_rules_modes() {
+        let mut index = rule("index.html", CacheRuleKind::Glob, "no-cache");
+        index.headers = Some(HashMap::from([("x-rule".to_string(), "index".to_string())]));
+        let mut rules = CacheRules {
+            rules: vec![
+                CacheRule::new(&index).unwrap(),
+                CacheRule::new(&rule("*.html", CacheRuleKind::Glob, "public")).unwrap(),
+            ],
+            mode: None,
+        };
+
+        let headers: HashMap<_, _> = rules.resolve("/index.html").into_iter().collect();
+        assert_eq!(headers[&header::CACHE_CONTROL], "no-cache");
+        assert_eq!(headers.len(), 2);
+
+        rules.mode = Some(CacheRulesMode::Merge);
+        let headers: HashMap<_, _> = rules.resolve("/index.html").into_iter().collect();
+        assert_eq!(headers[&header::CACHE_CONTROL], "public");
+        assert_eq!(headers[&HeaderName::from_static("x-rule")], "index");
+    }
+
+    #[actix_web::test]
+    async fn test_served_path() {
+        use crate::{core::configuration::StaticHubConfiguration, net::http_adapter::hub_scope};
+        use actix_web::{App, test};
+
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.path().join("docs/index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.path().join("app.js"), "").unwrap();
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str(&format!("path: {}\nspa: true", dir.path().display())).unwrap();
+        let hub = StaticHub::new(Some(&conf))
+            .unwrap()
+            .open_storage(Some(&conf))
+            .unwrap();
+        let rules = CacheRules {
+            rules: vec![
+                CacheRule::new(&rule("index.html", CacheRuleKind::Glob, "no-cache")).unwrap(),
+            ],
+            mode: None,
+        };
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(rules))
+                .wrap(CacheMiddleware)
+                .service(
+                    hub_scope("/")
+                        .app_data(Data::new(hub.clone()))
+                        .wrap(ServedPathMiddleware)
+                        .service(hub.files()),
+                ),
+        )
+        .await;
+
+        for uri in ["/", "/docs/", "/users/42"] {
+            let req = test::TestRequest::get().uri(uri).to_request();
+            let res = test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK, "{uri}");
+            assert_eq!(
+                res.headers().get(header::CACHE_CONTROL).unwrap(),
+                "no-cache",
+                "{uri}"
+            );
+        }
+
+        let req = test::TestRequest::get().uri("/app.js").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(!res.headers().contains_key(header::CACHE_CONTROL));
+    }
+}
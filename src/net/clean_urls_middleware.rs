@@ -0,0 +1,279 @@
+use std::{
+    future::{Ready, ready},
+    io,
+    pin::Pin,
+    rc::Rc,
+    str::FromStr,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{Method, Uri, header},
+    web::Data,
+};
+use percent_encoding::{AsciiSet, NON_ALPHANUMERIC, utf8_percent_encode};
+
+use crate::{
+    core::configuration::TrailingSlash,
+    net::{
+        static_hub::{StaticHub, is_asset, normalize},
+        storage::{Storage, join},
+    },
+};
+
+/// Characters of a path segment kept as is when the resolved path is written back.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// The path with or without trailing slash required by the policy, `None` when it complies.
+fn trailing_slash_redirect(policy: TrailingSlash, path: &str, relative: &str) -> Option<String> {
+    // the hub root keeps its slash whatever the policy
+    if relative.trim_matches('/').is_empty() {
+        return None;
+    }
+
+    match policy {
+        TrailingSlash::Add if !path.ends_with('/') && !is_asset(path) => Some(format!("{path}/")),
+        TrailingSlash::Remove if path.ends_with('/') => {
+            Some(path.trim_end_matches('/').to_string())
+        }
+        _ => None,
+    }
+}
+
+/// The path of the entry matching every segment of `path` regardless of case.
+async fn match_case(storage: &dyn Storage, path: &str) -> io::Result<Option<String>> {
+    let mut current = String::new();
+
+    for segment in path.split('/') {
+        let candidate = join(&current, segment);
+        if storage.metadata(&candidate).await?.is_some() {
+            current = candidate;
+            continue;
+        }
+
+        // a missing directory, or a file, has nothing to match
+        let Ok(entries) = storage.list(&current).await else {
+            return Ok(None);
+        };
+        match entries
+            .into_iter()
+            .find(|e| e.name.eq_ignore_ascii_case(segment))
+        {
+            Some(entry) => current = join(&current, &entry.name),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(current))
+}
+
+/// The existing file standing for a missing normalized path: its `.html` sibling with clean URLs,
+/// or the path with another case when case-insensitive.
+pub async fn resolve(
+    hub: &StaticHub,
+    storage: &dyn Storage,
+    path: &str,
+) -> io::Result<Option<String>> {
+    if path.is_empty() || storage.metadata(path).await?.is_some() {
+        return Ok(None);
+    }
+
+    let mut candidates = Vec::new();
+    if hub.case_insensitive {
+        candidates.push(path.to_string());
+    }
+    if hub.clean_urls && !path.ends_with(".html") {
+        candidates.push(format!("{path}.html"));
+    }
+
+    for candidate in candidates {
+        if candidate != path && storage.metadata(&candidate).await?.is_some() {
+            return Ok(Some(candidate));
+        }
+        if hub.case_insensitive
+            && let Some(found) = match_case(storage, &candidate).await?
+        {
+            return Ok(Some(found));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Points the request at another path of the hub, before the rewrite rules and the hub.
+pub fn rewrite_path(
+    req: &mut ServiceRequest,
+    relative: &str,
+) -> Result<(), actix_web::http::uri::InvalidUri> {
+    let matched = req.match_info().as_str();
+    let prefix = &matched[..matched.len() - req.match_info().unprocessed().len()];
+
+    let encoded: Vec<String> = relative
+        .split('/')
+        .map(|s| utf8_percent_encode(s, SEGMENT).to_string())
+        .collect();
+    let path = format!("{prefix}/{}", encoded.join("/"));
+    let uri = match req.query_string() {
+        "" => Uri::from_str(&path)?,
+        query => Uri::from_str(&format!("{path}?{query}"))?,
+    };
+
+    req.match_info_mut().get_mut().update(&uri);
+    req.head_mut().uri = uri;
+    Ok(())
+}
+
+/// Enforces the trailing slash policy of the static hub, and serves the `.html` file of clean
+/// URLs or the file matching a path regardless of case.
+pub struct CleanUrlsMiddleware;
+
+pub struct CleanUrlsMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CleanUrlsMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = CleanUrlsMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CleanUrlsMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for CleanUrlsMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .cloned()
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD));
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        if let Some(location) = trailing_slash_redirect(
+            hub.trailing_slash,
+            req.path(),
+            req.match_info().unprocessed(),
+        ) {
+            let location = match req.query_string() {
+                "" => location,
+                query => format!("{location}?{query}"),
+            };
+            let res = HttpResponse::PermanentRedirect()
+                .insert_header((header::LOCATION, location))
+                .finish();
+            return Box::pin(async move { Ok(req.into_response(res)) });
+        }
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let storage = hub
+                .storage
+                .clone()
+                .filter(|_| hub.clean_urls || hub.case_insensitive);
+
+            if let Some(storage) = storage
+                && let Some(path) = normalize(req.match_info().unprocessed())
+            {
+                match resolve(&hub, storage.as_ref(), &path).await {
+                    Ok(Some(resolved)) => {
+                        if let Err(e) = rewrite_path(&mut req, &resolved) {
+                            eprintln!("failed to serve '{resolved}' for '{path}': {e}");
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => eprintln!("failed to resolve '{path}': {e}"),
+                }
+            }
+
+            service.call(req).await.map(|res| res.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::storage::memory::MemoryStorage;
+    use actix_web::web::Bytes;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_trailing_slash_redirect() {
+        let redirect = trailing_slash_redirect;
+        assert_eq!(
+            redirect(TrailingSlash::Add, "/docs", "/docs"),
+            Some("/docs/".to_string())
+        );
+        assert_eq!(redirect(TrailingSlash::Add, "/app.js", "/app.js"), None);
+        assert_eq!(
+            redirect(TrailingSlash::Remove, "/blog/docs/", "/docs/"),
+            Some("/blog/docs".to_string())
+        );
+        assert_eq!(redirect(TrailingSlash::Remove, "/blog/", "/"), None);
+        assert_eq!(redirect(TrailingSlash::Leave, "/docs", "/docs"), None);
+    }
+
+    #[actix_web::test]
+    async fn test_resolve() {
+        let mut storage = MemoryStorage::default();
+        storage.insert("about.html", Bytes::from_static(b"about"), None);
+        storage.insert("Docs/Guide.html", Bytes::from_static(b"guide"), None);
+
+        let mut hub = StaticHub::new(None).unwrap();
+        hub.clean_urls = true;
+        hub.case_insensitive = true;
+        hub.storage = Some(Arc::new(storage));
+        let storage = hub.storage.clone().unwrap();
+
+        let resolved = |path: &'static str| {
+            let (hub, storage) = (hub.clone(), storage.clone());
+            async move { resolve(&hub, storage.as_ref(), path).await.unwrap() }
+        };
+        assert_eq!(resolved("about").await, Some("about.html".to_string()));
+        assert_eq!(resolved("about.html").await, None);
+        assert_eq!(
+            resolved("docs/guide").await,
+            Some("Docs/Guide.html".to_string())
+        );
+        assert_eq!(
+            resolved("DOCS/GUIDE.HTML").await,
+            Some("Docs/Guide.html".to_string())
+        );
+        assert_eq!(resolved("missing").await, None);
+    }
+}
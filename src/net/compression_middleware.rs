@@ -0,0 +1,471 @@
+use std::{
+    future::{Ready, ready},
+    io::{self, Write},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method, StatusCode,
+        header::{self, ContentEncoding, HeaderMap, HeaderValue},
+    },
+    web::{Bytes, Data},
+};
+
+use crate::{
+    core::configuration::{CompressionConfiguration, Configuration},
+    net::http_adapter::{Hub, HubRoutes},
+};
+
+const DEFAULT_MIN_SIZE: usize = 1024;
+const DEFAULT_ENCODINGS: [ContentEncoding; 3] = [
+    ContentEncoding::Brotli,
+    ContentEncoding::Zstd,
+    ContentEncoding::Gzip,
+];
+const DEFAULT_MIME_TYPES: [&str; 6] = [
+    "text/*",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "application/wasm",
+    "image/svg+xml",
+];
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const BROTLI_WINDOW_SIZE: u32 = 22;
+
+/// How the responses of a hub are compressed.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    /// The encodings offered, by order of preference.
+    encodings: Vec<ContentEncoding>,
+    min_size: usize,
+    mime_types: Vec<String>,
+    level: Option<u32>,
+}
+
+impl CompressionPolicy {
+    pub fn new(conf: &CompressionConfiguration) -> io::Result<Self> {
+        let encodings = match conf.encodings.as_ref() {
+            Some(encodings) => encodings
+                .iter()
+                .map(|e| parse_encoding(e))
+                .collect::<io::Result<Vec<_>>>()?,
+            None => DEFAULT_ENCODINGS.to_vec(),
+        };
+
+        let mime_types = conf
+            .mime_types
+            .clone()
+            .unwrap_or_else(|| DEFAULT_MIME_TYPES.iter().map(|m| m.to_string()).collect())
+            .into_iter()
+            .map(|m| m.to_ascii_lowercase())
+            .collect();
+
+        Ok(Self {
+            encodings,
+            min_size: conf.min_size.unwrap_or(DEFAULT_MIN_SIZE),
+            mime_types,
+            level: conf.level,
+        })
+    }
+
+    /// Whether a response is worth compressing under this policy.
+    fn applies(&self, res: &ServiceResponse) -> bool {
+        let headers = res.headers();
+
+        // already encoded (e.g. by the upstream or a precompressed sibling), partial or uncacheable transforms
+        if headers.contains_key(header::CONTENT_ENCODING)
+            || headers.contains_key(header::CONTENT_RANGE)
+            || headers
+                .get(header::CACHE_CONTROL)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| h.to_ascii_lowercase().contains("no-transform"))
+        {
+            return false;
+        }
+
+        if matches!(
+            res.status(),
+            StatusCode::NO_CONTENT | StatusCode::PARTIAL_CONTENT | StatusCode::NOT_MODIFIED
+        ) {
+            return false;
+        }
+
+        let large_enough = match res.response().body().size() {
+            BodySize::None => false,
+            BodySize::Sized(size) => size >= self.min_size as u64,
+            BodySize::Stream => true,
+        };
+
+        let content_type = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.split(';').next())
+            .map(|h| h.trim().to_ascii_lowercase());
+
+        large_enough
+            && content_type.is_some_and(|ct| self.mime_types.iter().any(|m| mime_matches(m, &ct)))
+    }
+}
+
+/// The compression policy of each hub, hubs without one are never compressed.
+#[derive(Debug, Clone, Default)]
+pub struct CompressionPolicies {
+    _static: Option<CompressionPolicy>,
+    configuration: Option<CompressionPolicy>,
+    upstream: Option<CompressionPolicy>,
+}
+
+impl CompressionPolicies {
+    pub fn new(configuration: &Configuration) -> io::Result<Self> {
+        let hubs = configuration.hubs.as_ref();
+        let policy =
+            |conf: Option<&CompressionConfiguration>| conf.map(CompressionPolicy::new).transpose();
+
+        Ok(Self {
+            _static: policy(
+                hubs.and_then(|h| h._static.as_ref())
+                    .and_then(|s| s.compression.as_ref()),
+            )?,
+            configuration: policy(
+                hubs.and_then(|h| h.configuration.as_ref())
+                    .and_then(|c| c.compression.as_ref()),
+            )?,
+            upstream: policy(
+                hubs.and_then(|h| h.upstream.as_ref())
+                    .and_then(|u| u.compression.as_ref()),
+            )?,
+        })
+    }
+
+    pub fn get(&self, hub: Hub) -> Option<&CompressionPolicy> {
+        match hub {
+            Hub::Static => self._static.as_ref(),
+            Hub::Configuration => self.configuration.as_ref(),
+            Hub::Upstream => self.upstream.as_ref(),
+        }
+    }
+}
+
+pub struct CompressionMiddleware;
+
+pub struct CompressionMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CompressionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = CompressionMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CompressionMiddlewareService { service }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for CompressionMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<HubRoutes>>()
+            .and_then(|r| r.hub_of(req.path()));
+
+        let policy = req
+            .app_data::<Data<CompressionPolicies>>()
+            .zip(hub)
+            .and_then(|(p, hub)| p.get(hub).cloned())
+            .filter(|_| req.method() != Method::HEAD);
+
+        let encoding = policy.as_ref().and_then(|p| {
+            let accept = req
+                .headers()
+                .get(header::ACCEPT_ENCODING)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default();
+            preferred_encoding(accept, &p.encodings)
+        });
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?.map_into_boxed_body();
+
+            let (Some(policy), Some(encoding)) = (policy, encoding) else {
+                return Ok(res);
+            };
+
+            if !policy.applies(&res) {
+                return Ok(res);
+            }
+
+            let encoder = Encoder::new(encoding, policy.level)?;
+
+            Ok(res.map_body(|head, body| {
+                let headers = &mut head.headers;
+                headers.remove(header::CONTENT_LENGTH);
+                headers.insert(header::CONTENT_ENCODING, encoding.to_header_value());
+                vary_accept_encoding(headers);
+
+                // the encoded representation is not byte-identical anymore
+                let weak = headers
+                    .get(header::ETAG)
+                    .and_then(|h| h.to_str().ok())
+                    .filter(|etag| !etag.starts_with("W/"))
+                    .and_then(|etag| HeaderValue::from_str(&format!("W/{etag}")).ok());
+                if let Some(weak) = weak {
+                    headers.insert(header::ETAG, weak);
+                }
+
+                BoxBody::new(EncodedBody {
+                    body,
+                    encoder: Some(encoder),
+                })
+            }))
+        })
+    }
+}
+
+/// Picks the candidate with the highest quality in an `Accept-Encoding` header,
+/// the order of the candidates breaking ties.
+pub fn preferred_encoding(accept: &str, candidates: &[ContentEncoding]) -> Option<ContentEncoding> {
+    let mut best: Option<(f32, ContentEncoding)> = None;
+
+    for encoding in candidates {
+        let q = quality(accept, encoding.as_str());
+        if q > 0.0 && best.is_none_or(|(b, _)| q > b) {
+            best = Some((q, *encoding));
+        }
+    }
+
+    best.map(|(_, encoding)| encoding)
+}
+
+/// Adds `Accept-Encoding` to the `Vary` header, unless already there.
+pub fn vary_accept_encoding(headers: &mut HeaderMap) {
+    let varies = headers.get_all(header::VARY).any(|h| {
+        h.to_str()
+            .is_ok_and(|h| h.to_ascii_lowercase().contains("accept-encoding"))
+    });
+
+    if !varies {
+        headers.append(header::VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+/// The quality of a coding in an `Accept-Encoding` header, 0 when not acceptable.
+fn quality(accept: &str, coding: &str) -> f32 {
+    let mut wildcard = None;
+
+    for entry in accept.split(',') {
+        let mut params = entry.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .find_map(|p| p.trim().strip_prefix("q="))
+            .and_then(|q| q.trim().parse().ok())
+            .unwrap_or(1.0);
+
+        if name.eq_ignore_ascii_case(coding) {
+            return q;
+        }
+        if name == "*" {
+            wildcard = Some(q);
+        }
+    }
+
+    wildcard.unwrap_or(0.0)
+}
+
+fn parse_encoding(name: &str) -> io::Result<ContentEncoding> {
+    match name.to_ascii_lowercase().as_str() {
+        "br" | "brotli" => Ok(ContentEncoding::Brotli),
+        "gzip" => Ok(ContentEncoding::Gzip),
+        "zstd" => Ok(ContentEncoding::Zstd),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported compression encoding '{name}', expected br, gzip or zstd"),
+        )),
+    }
+}
+
+fn mime_matches(pattern: &str, content_type: &str) -> bool {
+    match pattern.strip_suffix("/*") {
+        Some("*") => true,
+        Some(kind) => content_type.split_once('/').is_some_and(|(k, _)| k == kind),
+        None => pattern == content_type,
+    }
+}
+
+/// A streaming encoder, writing compressed data into an in-memory buffer drained after each chunk.
+enum Encoder {
+    Gzip(flate2::write::GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: ContentEncoding, level: Option<u32>) -> io::Result<Self> {
+        match encoding {
+            ContentEncoding::Brotli => {
+                Ok(Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                    Vec::new(),
+                    BROTLI_BUFFER_SIZE,
+                    level.unwrap_or(5).min(11),
+                    BROTLI_WINDOW_SIZE,
+                ))))
+            }
+            ContentEncoding::Zstd => Ok(Encoder::Zstd(zstd::stream::write::Encoder::new(
+                Vec::new(),
+                level.unwrap_or(3).clamp(1, 22) as i32,
+            )?)),
+            _ => Ok(Encoder::Gzip(flate2::write::GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(level.unwrap_or(6).min(9)),
+            ))),
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<Bytes> {
+        let buffer = match self {
+            Encoder::Gzip(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+            Encoder::Brotli(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+            Encoder::Zstd(e) => {
+                e.write_all(data)?;
+                e.get_mut()
+            }
+        };
+        Ok(Bytes::from(std::mem::take(buffer)))
+    }
+
+    fn finish(self) -> io::Result<Bytes> {
+        let buffer = match self {
+            Encoder::Gzip(e) => e.finish()?,
+            Encoder::Brotli(e) => e.into_inner(),
+            Encoder::Zstd(e) => e.finish()?,
+        };
+        Ok(Bytes::from(buffer))
+    }
+}
+
+/// A body compressed on the fly, chunk by chunk.
+struct EncodedBody {
+    body: BoxBody,
+    encoder: Option<Encoder>,
+}
+
+impl MessageBody for EncodedBody {
+    type Error = Box<dyn std::error::Error>;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            let Some(encoder) = this.encoder.as_mut() else {
+                return Poll::Ready(None);
+            };
+
+            match std::task::ready!(Pin::new(&mut this.body).poll_next(cx)) {
+                Some(Ok(chunk)) => match encoder.write(&chunk) {
+                    // the encoder may buffer small chunks, keep reading until it outputs something
+                    Ok(encoded) if encoded.is_empty() => continue,
+                    Ok(encoded) => return Poll::Ready(Some(Ok(encoded))),
+                    Err(e) => return Poll::Ready(Some(Err(e.into()))),
+                },
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => {
+                    // taken so that the next poll ends the body
+                    let encoder = this.encoder.take().unwrap();
+                    return Poll::Ready(Some(encoder.finish().map_err(Into::into)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_preferred_encoding() {
+        let candidates = [ContentEncoding::Brotli, ContentEncoding::Gzip];
+
+        assert_eq!(
+            preferred_encoding("gzip, deflate, br", &candidates),
+            Some(ContentEncoding::Brotli)
+        );
+        assert_eq!(
+            preferred_encoding("gzip;q=1.0, br;q=0.5", &candidates),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            preferred_encoding("br;q=0, *;q=0.1", &candidates),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(preferred_encoding("identity", &candidates), None);
+        assert_eq!(preferred_encoding("", &candidates), None);
+    }
+
+    #[test]
+    fn test_mime_matches() {
+        assert!(mime_matches("text/*", "text/html"));
+        assert!(mime_matches("application/json", "application/json"));
+        assert!(mime_matches("*/*", "image/png"));
+        assert!(!mime_matches("text/*", "image/png"));
+    }
+
+    #[actix_web::test]
+    async fn test_encoded_body() {
+        let data = "sthub ".repeat(1000);
+        let body = EncodedBody {
+            body: BoxBody::new(data.clone()),
+            encoder: Some(Encoder::new(ContentEncoding::Gzip, None).unwrap()),
+        };
+
+        let encoded = actix_web::body::to_bytes(body).await.unwrap();
+        assert!(encoded.len() < data.len());
+
+        let mut decoded = String::new();
+        flate2::read::GzDecoder::new(&encoded[..])
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, data);
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    io::Read,
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method, StatusCode,
+        header::{self, HeaderValue},
+    },
+    web::Data,
+};
+use sha2::{Digest, Sha256};
+
+use crate::net::static_hub::StaticHub;
+
+/// Hex digits of the SHA-256 kept in the ETag.
+const ETAG_HASH_LENGTH: usize = 32;
+const HASH_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Content hashes of the served files, computed on first use and recomputed
+/// when the size or modification time of a file changes.
+#[derive(Debug, Default)]
+pub struct ContentHashes {
+    hashes: Mutex<HashMap<PathBuf, ContentHash>>,
+}
+
+#[derive(Debug, Clone)]
+struct ContentHash {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: String,
+}
+
+impl ContentHashes {
+    pub async fn get(&self, path: &Path) -> Option<String> {
+        let metadata = tokio::fs::metadata(path)
+            .await
+            .ok()
+            .filter(|m| m.is_file())?;
+        let (len, modified) = (metadata.len(), metadata.modified().ok());
+
+        if let Some(cached) = self.hashes.lock().unwrap().get(path)
+            && cached.len == len
+            && cached.modified == modified
+        {
+            return Some(cached.hash.clone());
+        }
+
+        let owned = path.to_path_buf();
+        let hash = tokio::task::spawn_blocking(move || hash_file(&owned))
+            .await
+            .ok()?
+            .ok()?;
+
+        self.hashes.lock().unwrap().insert(
+            path.to_path_buf(),
+            ContentHash {
+                len,
+                modified,
+                hash: hash.clone(),
+            },
+        );
+        Some(hash)
+    }
+}
+
+fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; HASH_BUFFER_SIZE];
+
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect();
+    Ok(hash[..ETAG_HASH_LENGTH].to_string())
+}
+
+/// Replaces the mtime based ETags of the static hub by strong ones computed from the file
+/// contents, so that replicas serving the same build agree on their validators.
+pub struct ContentEtagMiddleware;
+
+pub struct ContentEtagMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ContentEtagMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ContentEtagMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ContentEtagMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ContentEtagMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .cloned()
+            .filter(|h| h.content_hashes.is_some())
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD));
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let relative = req.match_info().unprocessed().to_string();
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let hash = match (
+                hub.served_file(&relative).await,
+                hub.content_hashes.as_ref(),
+            ) {
+                (Some(path), Some(hashes)) => hashes.get(&path).await,
+                _ => None,
+            };
+            let Some(hash) = hash else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            // the files service compares the preconditions with its own validators, which are
+            // not the content hash: they are evaluated here instead
+            let headers = req.headers_mut();
+            let if_match = headers
+                .get(header::IF_MATCH)
+                .and_then(|h| h.to_str().ok())
+                .map(str::to_string);
+            headers.remove(header::IF_MATCH);
+
+            let if_range = headers
+                .get(header::IF_RANGE)
+                .and_then(|h| h.to_str().ok())
+                .filter(|h| h.starts_with('"') || h.starts_with("W/"))
+                .map(str::to_string);
+            if let Some(if_range) = if_range {
+                headers.remove(header::IF_RANGE);
+                // a range of another version of the file is answered with the whole file,
+                // the tags of precompressed siblings being suffixed with their encoding
+                let current = if_range == format!("\"{hash}\"")
+                    || if_range.starts_with(&format!("\"{hash}-"));
+                if !current {
+                    headers.remove(header::RANGE);
+                }
+            }
+
+            let res = service.call(req).await?.map_into_boxed_body();
+            if !matches!(res.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT) {
+                return Ok(res);
+            }
+
+            // a precompressed sibling is another representation of the file, with its own tag
+            let etag = match res
+                .headers()
+                .get(header::CONTENT_ENCODING)
+                .and_then(|h| h.to_str().ok())
+            {
+                Some(encoding) => format!("\"{hash}-{encoding}\""),
+                None => format!("\"{hash}\""),
+            };
+
+            let not_modified = res
+                .request()
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| etag_matches(h, &etag));
+            let failed = if_match.is_some_and(|h| !strong_etag_matches(&h, &etag));
+
+            let (req, mut res) = res.into_parts();
+            // the tag is built from hex digits and an encoding token
+            let etag = HeaderValue::from_str(&etag).unwrap();
+
+            if failed {
+                let failed = HttpResponse::PreconditionFailed()
+                    .insert_header((header::ETAG, etag))
+                    .finish();
+                return Ok(ServiceResponse::new(req, failed));
+            }
+
+            res.headers_mut().insert(header::ETAG, etag);
+
+            if not_modified {
+                let mut not_modified = HttpResponse::NotModified().finish();
+                for (name, value) in res.headers() {
+                    if name != header::CONTENT_LENGTH && name != header::CONTENT_TYPE {
+                        not_modified
+                            .headers_mut()
+                            .append(name.clone(), value.clone());
+                    }
+                }
+                return Ok(ServiceResponse::new(req, not_modified));
+            }
+
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+/// Weak comparison of an `If-None-Match` header with a tag, as required for this header.
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+}
+
+/// Strong comparison of an `If-Match` header with a tag, weak tags never matching.
+fn strong_etag_matches(if_match: &str, etag: &str) -> bool {
+    if_match
+        .split(',')
+        .map(|t| t.trim())
+        .any(|t| t == "*" || t == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::configuration::StaticHubConfiguration;
+    use actix_web::{App, test as actix_test};
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(etag_matches("W/\"a\"", "\"a\""));
+        assert!(etag_matches("*", "\"a\""));
+        assert!(!etag_matches("\"a-br\"", "\"a\""));
+
+        assert!(strong_etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(!strong_etag_matches("W/\"a\"", "\"a\""));
+    }
+
+    #[actix_web::test]
+    async fn test_content_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let file = dir.join("app.js");
+        std::fs::write(&file, "console.log('sthub')").unwrap();
+
+        let hashes = ContentHashes::default();
+        let first = hashes.get(&file).await.unwrap();
+        assert_eq!(first.len(), ETAG_HASH_LENGTH);
+        assert_eq!(hashes.get(&file).await, Some(first.clone()));
+
+        std::fs::write(&file, "console.log('sthub v2')").unwrap();
+        assert_ne!(hashes.get(&file).await, Some(first));
+        assert!(hashes.get(&dir.join("missing.js")).await.is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_content_etag() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str(&format!("path: {}\ncontent_etag: true", dir.display())).unwrap();
+        let hub = StaticHub::new(Some(&conf)).unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(Data::new(hub.clone()))
+                .wrap(ContentEtagMiddleware)
+                .service(hub.files()),
+        )
+        .await;
+
+        let req = actix_test::TestRequest::get().uri("/").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        let etag = res.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(etag.len(), ETAG_HASH_LENGTH + 2);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/index.html")
+            .insert_header((header::IF_NONE_MATCH, etag.clone()))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(res.headers().get(header::ETAG), Some(&etag));
+
+        // resumed downloads are validated against the content hash
+        let req = actix_test::TestRequest::get()
+            .uri("/index.html")
+            .insert_header((header::RANGE, "bytes=0-5"))
+            .insert_header((header::IF_RANGE, etag.clone()))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(actix_test::read_body(res).await, "<html>");
+
+        let req = actix_test::TestRequest::get()
+            .uri("/index.html")
+            .insert_header((header::RANGE, "bytes=0-5"))
+            .insert_header((header::IF_RANGE, "\"outdated\""))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(actix_test::read_body(res).await, "<html></html>");
+
+        let req = actix_test::TestRequest::get()
+            .uri("/index.html")
+            .insert_header((header::IF_MATCH, etag.clone()))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/index.html")
+            .insert_header((header::IF_MATCH, "\"outdated\""))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_files::{NamedFile, file_extension_to_mime};
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::header::{self, HeaderValue},
+    mime::Mime,
+    web::Data,
+};
+
+use crate::{core::configuration::StaticHubConfiguration, net::static_hub::StaticHub};
+
+const DEFAULT_CHARSET: &str = "utf-8";
+/// The charset policy declaring no charset at all.
+const NO_CHARSET: &str = "none";
+
+/// The content types of the static hub files: the built-in ones by extension, overridden by the
+/// configured ones, with the charset of text types.
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    /// By lowercase extension, without dot.
+    overrides: HashMap<String, Mime>,
+    charset: Option<String>,
+}
+
+impl Default for MimeTypes {
+    fn default() -> Self {
+        Self {
+            overrides: HashMap::new(),
+            charset: Some(DEFAULT_CHARSET.to_string()),
+        }
+    }
+}
+
+impl MimeTypes {
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Self> {
+        let mut errors = Vec::new();
+
+        let mut overrides = HashMap::new();
+        for (extension, mime) in conf
+            .and_then(|c| c.mime_types.as_ref())
+            .into_iter()
+            .flatten()
+        {
+            let extension = extension.trim_start_matches('.').to_lowercase();
+            if extension.is_empty() || extension.contains('/') {
+                errors.push(format!("invalid MIME type extension '{extension}'"));
+                continue;
+            }
+            match mime.parse::<Mime>() {
+                Ok(mime) => {
+                    overrides.insert(extension, mime);
+                }
+                Err(e) => errors.push(format!(
+                    "invalid MIME type '{mime}' for '.{extension}': {e}"
+                )),
+            }
+        }
+
+        let charset = match conf.and_then(|c| c.charset.as_deref()) {
+            None => Some(DEFAULT_CHARSET.to_string()),
+            Some(c) if c.eq_ignore_ascii_case(NO_CHARSET) => None,
+            Some(c)
+                if !c.is_empty()
+                    && c.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c)) =>
+            {
+                Some(c.to_lowercase())
+            }
+            Some(c) => {
+                errors.push(format!("invalid charset '{c}'"));
+                None
+            }
+        };
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid MIME types:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Self { overrides, charset })
+    }
+
+    /// The configured MIME type of a path, `None` when its extension has none.
+    fn override_for(&self, path: &str) -> Option<&Mime> {
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let (_, extension) = name.rsplit_once('.')?;
+        self.overrides.get(&extension.to_lowercase())
+    }
+
+    /// The MIME type of a path, without the charset policy applied.
+    pub fn mime(&self, path: &str) -> Mime {
+        match self.override_for(path) {
+            Some(mime) => mime.clone(),
+            None => {
+                let extension = path.rsplit_once('.').map(|(_, e)| e).unwrap_or_default();
+                file_extension_to_mime(extension)
+            }
+        }
+    }
+
+    /// Declares the charset of the policy on text types, unless they already declare their own.
+    pub fn with_charset(&self, mime: &Mime) -> String {
+        let essence = mime.essence_str();
+        let text = mime.type_() == "text"
+            || matches!(essence, "application/javascript" | "application/json")
+            || essence.ends_with("+json")
+            || essence.ends_with("+xml");
+
+        match &self.charset {
+            Some(charset) if text && mime.get_param("charset").is_none() => {
+                format!("{mime}; charset={charset}")
+            }
+            _ => mime.to_string(),
+        }
+    }
+
+    /// The `Content-Type` of a path.
+    pub fn content_type(&self, path: &str) -> String {
+        self.with_charset(&self.mime(path))
+    }
+
+    /// Sets the `Content-Type` of a file served by `actix_files`.
+    pub fn named_file(&self, file: NamedFile) -> NamedFile {
+        let content_type = self.content_type(&file.path().to_string_lossy());
+        match content_type.parse::<Mime>() {
+            Ok(mime) => file.set_content_type(mime),
+            Err(_) => file,
+        }
+    }
+}
+
+/// Applies the MIME types and the charset policy of the static hub to the files served by
+/// `actix_files`, which only knows its built-in types.
+pub struct ContentTypeMiddleware;
+
+pub struct ContentTypeMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ContentTypeMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ContentTypeMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ContentTypeMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ContentTypeMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .cloned()
+            .filter(|h| !h.embedded && h.serves_local_directory());
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        // the rewritten path is the one of the file served
+        let configured = hub
+            .mime_types
+            .override_for(req.match_info().unprocessed())
+            .cloned();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?.map_into_boxed_body();
+            if !res.status().is_success() {
+                return Ok(res);
+            }
+
+            let served = res
+                .headers()
+                .get(header::CONTENT_TYPE)
+                .and_then(|h| h.to_str().ok())
+                .and_then(|h| h.parse::<Mime>().ok());
+            let Some(served) = served else {
+                return Ok(res);
+            };
+
+            // the charset added by `actix_files` gives way to the policy
+            let mime = configured
+                .or_else(|| served.essence_str().parse().ok())
+                .unwrap_or(served);
+            if let Ok(value) = HeaderValue::from_str(&hub.mime_types.with_charset(&mime)) {
+                res.headers_mut().insert(header::CONTENT_TYPE, value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_type() {
+        let conf: StaticHubConfiguration = serde_yaml::from_str(
+            r#"
+mime_types:
+  .webmanifest: application/manifest+json
+  geojson: application/geo+json
+  LOG: text/plain; charset=iso-8859-1
+"#,
+        )
+        .unwrap();
+        let mime_types = MimeTypes::new(Some(&conf)).unwrap();
+
+        assert_eq!(
+            mime_types.content_type("index.html"),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(mime_types.content_type("logo.png"), "image/png");
+        assert_eq!(
+            mime_types.content_type("site.webmanifest"),
+            "application/manifest+json; charset=utf-8"
+        );
+        assert_eq!(
+            mime_types.content_type("map.GeoJSON"),
+            "application/geo+json; charset=utf-8"
+        );
+        assert_eq!(
+            mime_types.content_type("server.log"),
+            "text/plain; charset=iso-8859-1"
+        );
+
+        let conf: StaticHubConfiguration = serde_yaml::from_str("charset: none").unwrap();
+        let mime_types = MimeTypes::new(Some(&conf)).unwrap();
+        assert_eq!(mime_types.content_type("assets/app.js"), "text/javascript");
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("mime_types: {wasm: not a type}\ncharset: 'utf 8'").unwrap();
+        assert!(MimeTypes::new(Some(&conf)).is_err());
+    }
+}
@@ -0,0 +1,414 @@
+use std::{
+    cmp::Ordering,
+    fmt::Write,
+    io,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_files::{Directory, NamedFile, file_extension_to_mime};
+use actix_web::{
+    HttpRequest, HttpResponse, dev::ServiceResponse, http::header::HttpDate, web::Data,
+};
+use percent_encoding::{NON_ALPHANUMERIC, utf8_percent_encode};
+use serde::Serialize;
+
+use crate::{
+    core::configuration::ListingConfiguration,
+    net::{
+        access_middleware::AccessPolicy,
+        html_escape, prefers_json,
+        storage::{Storage, join},
+    },
+};
+
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+
+/// Which directories of the static hub are listed, and how.
+#[derive(Debug, Clone)]
+pub struct DirectoryListing {
+    /// Directories relative to the hub directory, listed with their subdirectories.
+    paths: Vec<String>,
+    show_hidden: bool,
+    max_entries: usize,
+}
+
+#[derive(Debug, Serialize)]
+struct Entry {
+    name: String,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    size: u64,
+    /// Seconds since the UNIX epoch.
+    modified: Option<u64>,
+    mime: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct Listing<'a> {
+    path: &'a str,
+    entries: &'a [Entry],
+    /// Set when entries were left out because of `max_entries`.
+    truncated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl DirectoryListing {
+    pub fn new(conf: &ListingConfiguration) -> Self {
+        let paths = conf
+            .paths
+            .clone()
+            .unwrap_or_else(|| vec!["/".to_string()])
+            .iter()
+            .map(|p| p.trim_matches('/').to_string())
+            .collect();
+
+        Self {
+            paths,
+            show_hidden: conf.show_hidden.unwrap_or(false),
+            max_entries: conf.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES),
+        }
+    }
+
+    /// Whether a directory, relative to the hub directory, may be listed.
+    pub fn allows(&self, relative: &Path) -> bool {
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let relative = relative.trim_matches('/');
+
+        self.paths
+            .iter()
+            .any(|p| p.is_empty() || relative == p || relative.starts_with(&format!("{p}/")))
+    }
+
+    /// Renders the listing of a directory without index document, or answers like a missing
+    /// file (SPA fallback or 404) for the directories that are not listed.
+    pub fn render(
+        &self,
+        dir: &Directory,
+        req: &HttpRequest,
+        fallback: Option<&Path>,
+    ) -> io::Result<ServiceResponse> {
+        let relative = dir.path.strip_prefix(&dir.base).unwrap_or(&dir.path);
+
+        if !self.allows(relative) {
+            let res = match fallback {
+                Some(document) => NamedFile::open(document)?.into_response(req),
+                None => HttpResponse::NotFound().finish(),
+            };
+            return Ok(ServiceResponse::new(req.clone(), res));
+        }
+
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        let access = req.app_data::<Data<AccessPolicy>>();
+        let entries = self.entries(&dir.path, |name| {
+            self.is_listed(access, &join(relative.trim_matches('/'), name))
+        })?;
+        Ok(ServiceResponse::new(
+            req.clone(),
+            respond(req, entries, self.max_entries),
+        ))
+    }
+
+    /// Renders the listing of a directory of a storage, `dir` being relative to its root.
+    pub async fn render_storage(
+        &self,
+        storage: &dyn Storage,
+        dir: &str,
+        req: &HttpRequest,
+    ) -> io::Result<HttpResponse> {
+        let access = req.app_data::<Data<AccessPolicy>>();
+        let mut entries = storage.list(dir).await?;
+        entries.retain(|e| self.is_listed(access, &join(dir, &e.name)));
+
+        let entries = entries
+            .into_iter()
+            .map(|e| {
+                listing_entry(
+                    e.name,
+                    e.metadata.is_dir(),
+                    e.metadata.size,
+                    e.metadata.modified,
+                )
+            })
+            .collect();
+
+        Ok(respond(req, entries, self.max_entries))
+    }
+
+    /// Whether an entry, relative to the hub root, is listed: hidden entries only with
+    /// `show_hidden`, and never the paths denied by the access policy.
+    fn is_listed(&self, access: Option<&Data<AccessPolicy>>, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or_default();
+
+        (self.show_hidden || !name.starts_with('.')) && access.is_none_or(|a| a.permits(path))
+    }
+
+    fn entries(&self, path: &Path, listed: impl Fn(&str) -> bool) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+
+        for entry in path.read_dir()? {
+            let Ok(entry) = entry else { continue };
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !listed(&name) {
+                continue;
+            }
+            // symlinks are described by their target
+            let Ok(metadata) = std::fs::metadata(entry.path()) else {
+                continue;
+            };
+            entries.push(listing_entry(
+                name,
+                metadata.is_dir(),
+                metadata.len(),
+                metadata.modified().ok(),
+            ));
+        }
+
+        Ok(entries)
+    }
+}
+
+fn listing_entry(name: String, is_dir: bool, size: u64, modified: Option<SystemTime>) -> Entry {
+    Entry {
+        mime: (!is_dir).then(|| {
+            let extension = Path::new(&name)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default();
+            file_extension_to_mime(extension).to_string()
+        }),
+        kind: if is_dir { "directory" } else { "file" },
+        size: if is_dir { 0 } else { size },
+        modified: modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs()),
+        name,
+    }
+}
+
+/// The sorted listing, as JSON when the client prefers it, truncated to its first `max_entries`
+/// once sorted.
+fn respond(req: &HttpRequest, mut entries: Vec<Entry>, max_entries: usize) -> HttpResponse {
+    let (key, descending) = sort_query(req.query_string());
+    sort_entries(&mut entries, key, descending);
+    let truncated = entries.len() > max_entries;
+    entries.truncate(max_entries);
+
+    if prefers_json(req.headers()) {
+        HttpResponse::Ok().json(Listing {
+            path: req.path(),
+            entries: &entries,
+            truncated,
+        })
+    } else {
+        HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(html(req.path(), &entries, truncated, key, descending))
+    }
+}
+
+/// The `sort` (`name`, `size` or `modified`) and `order` (`asc` or `desc`) query parameters.
+fn sort_query(query: &str) -> (SortKey, bool) {
+    let mut key = SortKey::Name;
+    let mut descending = false;
+
+    for (name, value) in query.split('&').filter_map(|p| p.split_once('=')) {
+        match (name, value) {
+            ("sort", "size") => key = SortKey::Size,
+            ("sort", "modified") => key = SortKey::Modified,
+            ("sort", _) => key = SortKey::Name,
+            ("order", "desc") => descending = true,
+            _ => {}
+        }
+    }
+
+    (key, descending)
+}
+
+/// Directories first, then by the sort key, the name breaking ties.
+fn sort_entries(entries: &mut [Entry], key: SortKey, descending: bool) {
+    entries.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+        }
+        .then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase()));
+
+        let ordering = if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        (a.kind != "directory")
+            .cmp(&(b.kind != "directory"))
+            .then(ordering)
+    });
+}
+
+fn html(path: &str, entries: &[Entry], truncated: bool, key: SortKey, descending: bool) -> String {
+    let base = path.trim_end_matches('/');
+    let title = html_escape(&format!(
+        "Index of {}",
+        if base.is_empty() { "/" } else { path }
+    ));
+
+    // a header link sorts by its column, ascending first, then toggles the order
+    let header = |label: &str, column: SortKey, name: &str| {
+        let order = if key == column && !descending {
+            "desc"
+        } else {
+            "asc"
+        };
+        format!("<th><a href=\"?sort={name}&amp;order={order}\">{label}</a></th>")
+    };
+
+    let mut body = String::new();
+    let _ = write!(
+        body,
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{title}</title></head><body>\
+         <h1>{title}</h1><table><thead><tr>{}{}{}<th>Type</th></tr></thead><tbody>",
+        header("Name", SortKey::Name, "name"),
+        header("Size", SortKey::Size, "size"),
+        header("Modified", SortKey::Modified, "modified"),
+    );
+
+    if !base.is_empty() {
+        let _ = write!(
+            body,
+            "<tr><td><a href=\"{}/\">../</a></td><td></td><td></td><td></td></tr>",
+            html_escape(base.rsplit_once('/').map(|(p, _)| p).unwrap_or_default())
+        );
+    }
+
+    for entry in entries {
+        let is_dir = entry.kind == "directory";
+        let href = format!(
+            "{base}/{}{}",
+            utf8_percent_encode(&entry.name, NON_ALPHANUMERIC),
+            if is_dir { "/" } else { "" }
+        );
+        let modified = entry
+            .modified
+            .map(|m| HttpDate::from(UNIX_EPOCH + Duration::from_secs(m)).to_string())
+            .unwrap_or_default();
+
+        let _ = write!(
+            body,
+            "<tr><td><a href=\"{}\">{}{}</a></td><td>{}</td><td>{modified}</td><td>{}</td></tr>",
+            html_escape(&href),
+            html_escape(&entry.name),
+            if is_dir { "/" } else { "" },
+            if is_dir {
+                String::new()
+            } else {
+                entry.size.to_string()
+            },
+            html_escape(entry.mime.as_deref().unwrap_or("directory")),
+        );
+    }
+
+    body.push_str("</tbody></table>");
+    if truncated {
+        body.push_str("<p>Some entries are not listed.</p>");
+    }
+    body.push_str("</body></html>");
+    body
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::configuration::StaticHubConfiguration, net::static_hub::StaticHub};
+    use actix_web::{
+        App,
+        http::{StatusCode, header},
+        test as actix_test,
+    };
+
+    #[test]
+    fn test_allows() {
+        let listing = DirectoryListing::new(&ListingConfiguration {
+            paths: Some(vec!["/artifacts/".to_string()]),
+            show_hidden: None,
+            max_entries: None,
+        });
+        assert!(listing.allows(Path::new("artifacts")));
+        assert!(listing.allows(Path::new("artifacts/v1")));
+        assert!(!listing.allows(Path::new("artifacts-old")));
+        assert!(!listing.allows(Path::new("")));
+    }
+
+    #[actix_web::test]
+    async fn test_directory_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::create_dir_all(dir.join("artifacts/nested")).unwrap();
+        std::fs::create_dir_all(dir.join("private")).unwrap();
+        std::fs::write(dir.join("artifacts/build.tar.gz"), "tarball").unwrap();
+        std::fs::write(dir.join("artifacts/.secret"), "hidden").unwrap();
+
+        let conf: StaticHubConfiguration = serde_yaml::from_str(&format!(
+            "path: {}\nlisting:\n  paths: [/artifacts]",
+            dir.display()
+        ))
+        .unwrap();
+        let hub = StaticHub::new(Some(&conf)).unwrap();
+        let app = actix_test::init_service(App::new().service(hub.files())).await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/artifacts/")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_request();
+        let listing: serde_json::Value = actix_test::call_and_read_body_json(&app, req).await;
+        let names: Vec<&str> = listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["nested", "build.tar.gz"]);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/artifacts/")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = actix_test::TestRequest::get().uri("/private/").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn test_truncated_listing() {
+        let entries = ["b.txt", "c.txt", "a.txt"]
+            .into_iter()
+            .map(|name| listing_entry(name.to_string(), false, 0, None))
+            .collect();
+
+        // the first entries in the requested order are kept, whatever the order read
+        let req = actix_test::TestRequest::get()
+            .uri("/?sort=name&order=desc")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_http_request();
+        let body = actix_web::body::to_bytes(respond(&req, entries, 2).into_body())
+            .await
+            .unwrap();
+        let listing: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let names: Vec<&str> = listing["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["c.txt", "b.txt"]);
+        assert_eq!(listing["truncated"], true);
+    }
+}
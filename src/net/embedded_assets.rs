@@ -0,0 +1,244 @@
+use std::rc::Rc;
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse, fn_service},
+    http::{
+        Method,
+        header::{self, ContentEncoding, HeaderValue},
+    },
+};
+
+use crate::{
+    core::configuration::Configuration,
+    net::{
+        compression_middleware::{preferred_encoding, vary_accept_encoding},
+        content_type_middleware::MimeTypes,
+        static_hub::StaticHub,
+    },
+};
+
+/// A file of the static directory bundled into the binary by the build script.
+#[derive(Debug)]
+pub struct EmbeddedAsset {
+    /// Relative to the bundled directory, without leading slash.
+    pub path: &'static str,
+    pub data: &'static [u8],
+    pub gzip: Option<&'static [u8]>,
+    pub brotli: Option<&'static [u8]>,
+    /// Hash of the content, computed at build time.
+    pub etag: &'static str,
+}
+
+// generated by `build.rs`, empty unless built with the `embed` feature
+include!(concat!(env!("OUT_DIR"), "/embedded_assets.rs"));
+
+/// Fails when a hub serves the embedded assets of a binary built without them.
+pub fn check(configuration: &Configuration) -> std::io::Result<()> {
+    let embedded = configuration
+        .hubs
+        .as_ref()
+        .and_then(|h| h._static.as_ref())
+        .and_then(|s| s.embedded)
+        .unwrap_or(false);
+
+    if embedded && !cfg!(feature = "embed") {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "the static hub serves embedded assets, but sthub was built without the `embed` feature",
+        ));
+    }
+
+    Ok(())
+}
+
+fn get(path: &str) -> Option<&'static EmbeddedAsset> {
+    ASSETS
+        .binary_search_by(|a| a.path.cmp(path))
+        .ok()
+        .map(|i| &ASSETS[i])
+}
+
+fn is_dir(path: &str) -> bool {
+    let prefix = format!("{path}/");
+    ASSETS.iter().any(|a| a.path.starts_with(&prefix))
+}
+
+/// The path of the asset served for a path relative to the remote path.
+pub fn served_path(hub: &StaticHub, relative: &str) -> Option<String> {
+    hub.lookup(relative, get, is_dir)
+        .map(|a| a.path.to_string())
+}
+
+fn respond(
+    req: &HttpRequest,
+    mime_types: &MimeTypes,
+    asset: Option<&'static EmbeddedAsset>,
+) -> HttpResponse {
+    if !matches!(*req.method(), Method::GET | Method::HEAD) {
+        return HttpResponse::MethodNotAllowed()
+            .insert_header((header::ALLOW, "GET, HEAD"))
+            .finish();
+    }
+    let Some(asset) = asset else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    let mut variants = Vec::new();
+    if asset.brotli.is_some() {
+        variants.push(ContentEncoding::Brotli);
+    }
+    if asset.gzip.is_some() {
+        variants.push(ContentEncoding::Gzip);
+    }
+
+    let accept = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or_default();
+
+    let (body, encoding) = match preferred_encoding(accept, &variants) {
+        Some(ContentEncoding::Brotli) => (asset.brotli.unwrap_or(asset.data), Some("br")),
+        Some(ContentEncoding::Gzip) => (asset.gzip.unwrap_or(asset.data), Some("gzip")),
+        _ => (asset.data, None),
+    };
+
+    // each encoding is a representation of its own, with its own tag
+    let etag = match encoding {
+        Some(encoding) => format!("\"{}-{encoding}\"", asset.etag),
+        None => format!("\"{}\"", asset.etag),
+    };
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| {
+            h.split(',')
+                .map(|t| t.trim())
+                .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+        });
+
+    let mut res = if not_modified {
+        HttpResponse::NotModified().finish()
+    } else {
+        let mut builder = HttpResponse::Ok();
+        builder.insert_header((header::CONTENT_TYPE, mime_types.content_type(asset.path)));
+        if let Some(encoding) = encoding {
+            builder.insert_header((header::CONTENT_ENCODING, encoding));
+        }
+        builder.body(body)
+    };
+
+    // the tag is built from hex digits and an encoding token
+    res.headers_mut()
+        .insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+    if !variants.is_empty() {
+        vary_accept_encoding(res.headers_mut());
+    }
+    res
+}
+
+/// Serves the assets bundled into the binary, from memory.
+pub fn service(
+    hub: StaticHub,
+) -> impl ServiceFactory<
+    ServiceRequest,
+    Config = (),
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+    InitError = (),
+> {
+    // shared by the requests rather than cloned for each of them
+    let hub = Rc::new(hub);
+    fn_service(move |req: ServiceRequest| {
+        let hub = hub.clone();
+
+        async move {
+            let asset = hub.lookup(req.match_info().unprocessed(), get, is_dir);
+            let (req, _pl) = req.into_parts();
+            let res = respond(&req, &hub.mime_types, asset);
+            Ok(ServiceResponse::new(req, res))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::TestRequest};
+
+    static ASSET: EmbeddedAsset = EmbeddedAsset {
+        path: "app.js",
+        data: b"console.log(1)",
+        gzip: Some(b"gzip"),
+        brotli: Some(b"brotli"),
+        etag: "abc",
+    };
+
+    fn header(res: &HttpResponse, name: header::HeaderName) -> Option<&str> {
+        res.headers().get(name).and_then(|h| h.to_str().ok())
+    }
+
+    #[actix_web::test]
+    async fn test_respond() {
+        let mime_types = MimeTypes::default();
+
+        let req = TestRequest::get()
+            .insert_header((header::ACCEPT_ENCODING, "gzip, br"))
+            .to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(&res, header::CONTENT_ENCODING), Some("br"));
+        assert_eq!(header(&res, header::ETAG), Some("\"abc-br\""));
+        assert_eq!(header(&res, header::VARY), Some("accept-encoding"));
+
+        let req = TestRequest::get()
+            .insert_header((header::ACCEPT_ENCODING, "gzip, br;q=0.5"))
+            .to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(header(&res, header::CONTENT_ENCODING), Some("gzip"));
+        assert_eq!(header(&res, header::ETAG), Some("\"abc-gzip\""));
+
+        let req = TestRequest::get().to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(header(&res, header::CONTENT_ENCODING), None);
+        assert_eq!(header(&res, header::ETAG), Some("\"abc\""));
+        assert!(header(&res, header::CONTENT_TYPE).is_some_and(|t| t.contains("javascript")));
+
+        // the tag of another encoding does not match
+        let req = TestRequest::get()
+            .insert_header((header::ACCEPT_ENCODING, "gzip"))
+            .insert_header((header::IF_NONE_MATCH, "\"abc-br\", W/\"abc-gzip\""))
+            .to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(res.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(header(&res, header::ETAG), Some("\"abc-gzip\""));
+
+        let req = TestRequest::get()
+            .insert_header((header::IF_NONE_MATCH, "\"abc-br\""))
+            .to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = TestRequest::post().to_http_request();
+        let res = respond(&req, &mime_types, Some(&ASSET));
+        assert_eq!(res.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(header(&res, header::ALLOW), Some("GET, HEAD"));
+
+        let req = TestRequest::get().to_http_request();
+        let res = respond(&req, &mime_types, None);
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn test_check() {
+        let conf: Configuration = serde_yaml::from_str("hubs: {static: {embedded: true}}").unwrap();
+        assert_eq!(check(&conf).is_ok(), cfg!(feature = "embed"));
+
+        let conf: Configuration =
+            serde_yaml::from_str("hubs: {static: {embedded: false}}").unwrap();
+        assert!(check(&conf).is_ok());
+    }
+}
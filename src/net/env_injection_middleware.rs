@@ -0,0 +1,252 @@
+use std::{
+    future::{Ready, ready},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    HttpMessage,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::header,
+    web::Data,
+};
+use serde_json::Value;
+
+use crate::{
+    core::configuration::{Configuration, StaticHubConfiguration},
+    net::{environment_middleware::environment_tree, html_document, static_hub::StaticHub},
+};
+
+const DEFAULT_VARIABLE: &str = "__ENV__";
+/// Replaced by the nonce of the response in the static hub headers.
+pub const NONCE_PLACEHOLDER: &str = "{nonce}";
+
+/// The CSP nonce of a response, kept in the request extensions for the `HeadersMiddleware`.
+#[derive(Debug, Clone)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    fn generate() -> Self {
+        let bytes: [u8; 16] = rand::random();
+        Self(bytes.iter().map(|b| format!("{b:02x}")).collect())
+    }
+}
+
+/// How the environment configuration tree is injected into the HTML documents of the hub.
+#[derive(Debug, Clone)]
+pub struct EnvInjection {
+    variable: String,
+    placeholder: Option<String>,
+    nonce: bool,
+}
+
+impl EnvInjection {
+    /// `None` when the injection is disabled.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Option<Self>> {
+        let Some(conf) = conf.and_then(|c| c.inject_env.as_ref()) else {
+            return Ok(None);
+        };
+
+        let variable = conf
+            .variable
+            .clone()
+            .unwrap_or_else(|| DEFAULT_VARIABLE.to_string());
+        let mut errors = Vec::new();
+        if !is_identifier(&variable) {
+            errors.push(format!("invalid variable name '{variable}'"));
+        }
+        if conf.placeholder.as_ref().is_some_and(|p| p.is_empty()) {
+            errors.push("empty placeholder".to_string());
+        }
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid environment injection:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Some(Self {
+            variable,
+            placeholder: conf.placeholder.clone(),
+            nonce: conf.nonce.unwrap_or(false),
+        }))
+    }
+
+    /// The script defining the variable, safe to embed in HTML.
+    fn script(&self, tree: &Value, nonce: Option<&CspNonce>) -> String {
+        // `<` and `>` only appear within JSON strings, where their escapes are equivalent
+        let json = tree
+            .to_string()
+            .replace('<', "\\u003c")
+            .replace('>', "\\u003e")
+            .replace('&', "\\u0026")
+            .replace('\u{2028}', "\\u2028")
+            .replace('\u{2029}', "\\u2029");
+
+        match nonce {
+            Some(nonce) => format!(
+                "<script nonce=\"{}\">window.{} = {json};</script>",
+                nonce.0, self.variable
+            ),
+            None => format!("<script>window.{} = {json};</script>", self.variable),
+        }
+    }
+
+    /// Inserts the script into a document, `None` when it has no place for it.
+    fn inject(&self, document: &str, script: &str) -> Option<String> {
+        match &self.placeholder {
+            Some(placeholder) => document
+                .contains(placeholder.as_str())
+                .then(|| document.replacen(placeholder.as_str(), script, 1)),
+            None => {
+                let end = document.to_ascii_lowercase().find("</head>")?;
+                Some(format!("{}{script}{}", &document[..end], &document[end..]))
+            }
+        }
+    }
+}
+
+/// Whether a name can follow `window.` in a script.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+/// Injects the environment configuration tree into the HTML documents of the static hub, saving
+/// applications the request to the configuration hub before they start.
+pub struct EnvInjectionMiddleware;
+
+pub struct EnvInjectionMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for EnvInjectionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = EnvInjectionMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(EnvInjectionMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for EnvInjectionMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let injection = req
+            .app_data::<Data<StaticHub>>()
+            .and_then(|h| h.inject_env.clone());
+
+        let Some(injection) = injection else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let nonce = injection.nonce.then(CspNonce::generate);
+        if let Some(nonce) = &nonce {
+            req.extensions_mut().insert(nonce.clone());
+        }
+
+        let document = html_document::may_be_document(req.method(), req.match_info().unprocessed());
+        if document {
+            html_document::request_whole(req.headers_mut());
+        }
+
+        let configuration = req.app_data::<Data<Configuration>>().cloned();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?.map_into_boxed_body();
+            if !document {
+                return Ok(res);
+            }
+
+            let mut injected = false;
+            let mut res = html_document::rewrite(res, async |document: &str| {
+                let tree = environment_tree(configuration.as_ref().map(|c| c.get_ref()));
+                let script = injection.script(&tree, nonce.as_ref());
+                let document = injection.inject(document, &script);
+                injected = document.is_some();
+                document
+            })
+            .await?;
+
+            // the document now changes with the nonce
+            if injected && nonce.is_some() {
+                res.headers_mut().insert(
+                    header::CACHE_CONTROL,
+                    header::HeaderValue::from_static("no-store"),
+                );
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_inject() {
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("inject_env: {variable: APP_ENV}").unwrap();
+        let injection = EnvInjection::new(Some(&conf)).unwrap().unwrap();
+
+        let script = injection.script(&json!({"api": "</script><b>&"}), None);
+        assert_eq!(
+            script,
+            r#"<script>window.APP_ENV = {"api":"\u003c/script\u003e\u003cb\u003e\u0026"};</script>"#
+        );
+        assert_eq!(
+            injection.inject("<html><HEAD><title>t</title></HEAD></html>", "<s/>"),
+            Some("<html><HEAD><title>t</title><s/></HEAD></html>".to_string())
+        );
+        assert_eq!(injection.inject("<p>fragment</p>", "<s/>"), None);
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("inject_env: {placeholder: '<!--env-->', nonce: true}").unwrap();
+        let injection = EnvInjection::new(Some(&conf)).unwrap().unwrap();
+        let nonce = CspNonce("abc".to_string());
+        assert_eq!(
+            injection.inject(
+                "<head><!--env--></head>",
+                &injection.script(&json!({}), Some(&nonce))
+            ),
+            Some(r#"<head><script nonce="abc">window.__ENV__ = {};</script></head>"#.to_string())
+        );
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("inject_env: {variable: 'a.b; alert(1)'}").unwrap();
+        assert!(EnvInjection::new(Some(&conf)).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    path::Path,
+    pin::Pin,
+    rc::Rc,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method,
+        header::{self, HeaderValue},
+    },
+    web::{Bytes, Data},
+};
+use regex::Regex;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    core::configuration::{Configuration, MissingVariable, StaticHubConfiguration},
+    net::{
+        cache_middleware::glob_regex,
+        environment_middleware::env_prefix,
+        static_hub::StaticHub,
+        storage::{Metadata, Storage},
+    },
+};
+
+const DEFAULT_START: &str = "${";
+const DEFAULT_END: &str = "}";
+/// Hex digits of the SHA-256 kept in the ETag.
+const ETAG_HASH_LENGTH: usize = 32;
+
+/// A substituted file, valid as long as the file is unchanged.
+#[derive(Debug, Clone)]
+struct Substituted {
+    size: u64,
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+    body: Bytes,
+    hash: String,
+}
+
+/// The files whose placeholders are replaced by environment variables, substituted on first use
+/// and again when they change.
+#[derive(Debug)]
+pub struct EnvSubstitution {
+    /// Matched against the whole path when the glob has a `/`, against the file name otherwise.
+    files: Vec<(Regex, bool)>,
+    start: String,
+    end: String,
+    missing: MissingVariable,
+    /// Keyed by the hub directory and the path, releases having files of the same name.
+    cache: Mutex<HashMap<String, Substituted>>,
+}
+
+impl EnvSubstitution {
+    /// `None` when the substitution is disabled.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Option<Self>> {
+        let Some(conf) = conf.and_then(|c| c.substitute_env.as_ref()) else {
+            return Ok(None);
+        };
+
+        let mut errors = Vec::new();
+        let files = conf
+            .files
+            .iter()
+            .filter_map(|glob| match glob_regex(glob) {
+                Ok(regex) => Some((regex, glob.contains('/'))),
+                Err(e) => {
+                    errors.push(format!("invalid substitution glob '{glob}': {e}"));
+                    None
+                }
+            })
+            .collect();
+
+        let start = conf
+            .start
+            .clone()
+            .unwrap_or_else(|| DEFAULT_START.to_string());
+        let end = conf.end.clone().unwrap_or_else(|| DEFAULT_END.to_string());
+        if start.is_empty() || end.is_empty() {
+            errors.push("empty placeholder delimiter".to_string());
+        }
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid environment substitution:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Some(Self {
+            files,
+            start,
+            end,
+            missing: conf.missing.unwrap_or(MissingVariable::Keep),
+            cache: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Whether a normalized path is substituted.
+    pub fn matches(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        self.files
+            .iter()
+            .any(|(regex, full_path)| regex.is_match(if *full_path { path } else { file_name }))
+    }
+
+    /// Replaces the placeholders of `text` by the values found by `lookup`.
+    fn substitute(&self, text: &str, lookup: impl Fn(&str) -> Option<String>) -> String {
+        let mut substituted = String::with_capacity(text.len());
+        let mut rest = text;
+
+        while let Some(start) = rest.find(&self.start) {
+            let after = &rest[start + self.start.len()..];
+            let name = after
+                .find(&self.end)
+                .map(|end| &after[..end])
+                .filter(|name| is_variable_name(name));
+
+            let Some(name) = name else {
+                substituted.push_str(&rest[..start + self.start.len()]);
+                rest = after;
+                continue;
+            };
+
+            let placeholder_len = self.start.len() + name.len() + self.end.len();
+            substituted.push_str(&rest[..start]);
+            match (lookup(name), self.missing) {
+                (Some(value), _) => substituted.push_str(&value),
+                (None, MissingVariable::Keep) => {
+                    substituted.push_str(&rest[start..start + placeholder_len])
+                }
+                (None, MissingVariable::Empty) => {}
+            }
+            rest = &rest[start + placeholder_len..];
+        }
+
+        substituted.push_str(rest);
+        substituted
+    }
+
+    /// The substituted body of a file and its hash, read again when the file changed.
+    pub async fn get(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        path: &str,
+        metadata: &Metadata,
+        prefix: &str,
+    ) -> std::io::Result<(Bytes, String)> {
+        let key = Path::new(&hub.path)
+            .join(path)
+            .to_string_lossy()
+            .into_owned();
+        if let Some(cached) = self.cache.lock().unwrap().get(&key)
+            && cached.size == metadata.size
+            && cached.modified == metadata.modified
+            && cached.etag == metadata.etag
+        {
+            return Ok((cached.body.clone(), cached.hash.clone()));
+        }
+
+        let bytes = storage.read(path, None).await?;
+        let body = match std::str::from_utf8(&bytes) {
+            Ok(text) => Bytes::from(self.substitute(text, |name| lookup(prefix, name))),
+            // binary files have no placeholders
+            Err(_) => bytes,
+        };
+        let hash: String = Sha256::digest(&body)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let hash = hash[..ETAG_HASH_LENGTH].to_string();
+
+        self.cache.lock().unwrap().insert(
+            key,
+            Substituted {
+                size: metadata.size,
+                modified: metadata.modified,
+                etag: metadata.etag.clone(),
+                body: body.clone(),
+                hash: hash.clone(),
+            },
+        );
+        Ok((body, hash))
+    }
+}
+
+fn is_variable_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The value of a variable exposed by the configuration hub, named with or without its prefix.
+/// Other variables are never read, so that files cannot disclose them.
+fn lookup(prefix: &str, name: &str) -> Option<String> {
+    let prefix = format!("{}__", prefix.trim_end_matches('_'));
+    let variable = if name.starts_with(&prefix) {
+        name.to_string()
+    } else {
+        format!("{prefix}{name}")
+    };
+    std::env::var(variable).ok()
+}
+
+/// Serves the files of the static hub with their placeholders replaced by environment variables,
+/// for bundles built with tokens to fill at deploy time.
+pub struct EnvSubstitutionMiddleware;
+
+pub struct EnvSubstitutionMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for EnvSubstitutionMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = EnvSubstitutionMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(EnvSubstitutionMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for EnvSubstitutionMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .cloned()
+            .filter(|h| h.substitute_env.is_some() && h.storage.is_some())
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD));
+        let relative = req.match_info().unprocessed().to_string();
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let prefix = env_prefix(req.app_data::<Data<Configuration>>().map(|c| c.get_ref()));
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let (Some(substitution), Some(storage)) = (&hub.substitute_env, &hub.storage) else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            let file = match hub.stored_file(&relative).await {
+                Ok(file) => file.filter(|(file, _)| substitution.matches(file)),
+                Err(e) => {
+                    eprintln!("failed to look up '{relative}' for substitution: {e}");
+                    None
+                }
+            };
+            let Some((file, metadata)) = file else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            let (body, hash) = substitution
+                .get(&hub, storage.as_ref(), &file, &metadata, &prefix)
+                .await?;
+            let etag = format!("\"{hash}\"");
+
+            let not_modified = req
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| {
+                    h.split(',')
+                        .map(|t| t.trim())
+                        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+                });
+
+            let mut res = match not_modified {
+                true => HttpResponse::NotModified().finish(),
+                false => HttpResponse::Ok()
+                    .insert_header((header::CONTENT_TYPE, hub.mime_types.content_type(&file)))
+                    .body(body),
+            };
+            // the tag is built from hex digits
+            res.headers_mut()
+                .insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+
+            let (req, _pl) = req.into_parts();
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{App, http::StatusCode, test as actix_test};
+
+    #[test]
+    fn test_substitute() {
+        let conf: StaticHubConfiguration = serde_yaml::from_str(
+            "substitute_env: {files: ['*.js', '/config/*.json'], start: __, end: __}",
+        )
+        .unwrap();
+        let substitution = EnvSubstitution::new(Some(&conf)).unwrap().unwrap();
+
+        assert!(substitution.matches("assets/app.js"));
+        assert!(substitution.matches("config/app.json"));
+        assert!(!substitution.matches("assets/config/app.json"));
+
+        let lookup = |name: &str| (name == "API_URL").then(|| "https://api".to_string());
+        assert_eq!(
+            substitution.substitute("fetch('__API_URL__/__MISSING__', __dirname __)", lookup),
+            "fetch('https://api/__MISSING__', __dirname __)"
+        );
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("substitute_env: {files: ['*'], missing: empty}").unwrap();
+        let substitution = EnvSubstitution::new(Some(&conf)).unwrap().unwrap();
+        assert_eq!(
+            substitution.substitute("${API_URL}${NONE}${not a name}$", lookup),
+            "https://api${not a name}$"
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_substitution_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join("index.html"),
+            "<base href=\"${TEST_SUBSTITUTION_FALLBACK}\">",
+        )
+        .unwrap();
+        // SAFETY: the variables are only read by this test
+        unsafe { std::env::set_var("STHUB__TEST_SUBSTITUTION_FALLBACK", "/app/") };
+
+        let conf: StaticHubConfiguration = serde_yaml::from_str(&format!(
+            "path: {}\nfallback: /index.html\nsubstitute_env: {{files: ['*.html']}}",
+            dir.path().display()
+        ))
+        .unwrap();
+        let hub = StaticHub::new(Some(&conf))
+            .and_then(|h| h.open_storage(Some(&conf)))
+            .unwrap();
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(Data::new(hub.clone()))
+                .wrap(EnvSubstitutionMiddleware)
+                .service(hub.files()),
+        )
+        .await;
+
+        for uri in ["/", "/dashboard"] {
+            let req = actix_test::TestRequest::get().uri(uri).to_request();
+            let res = actix_test::call_service(&app, req).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(actix_test::read_body(res).await, "<base href=\"/app/\">");
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        // SAFETY: the variables are only read by this test
+        unsafe { std::env::set_var("STHUB__TEST_LOOKUP", "exposed") };
+        assert_eq!(
+            lookup("STHUB__", "TEST_LOOKUP"),
+            Some("exposed".to_string())
+        );
+        assert_eq!(
+            lookup("STHUB", "STHUB__TEST_LOOKUP"),
+            Some("exposed".to_string())
+        );
+        assert_eq!(lookup("STHUB__", "PATH"), None);
+    }
+}
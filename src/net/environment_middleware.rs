@@ -7,10 +7,27 @@ use actix_web::{
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     web,
 };
+use serde_json::Value;
 use std::future::{Future, Ready, ready};
 use std::pin::Pin;
 use std::task::{Context, Poll};
 
+/// The prefix of the environment variables exposed by the configuration hub.
+pub fn env_prefix(conf: Option<&Configuration>) -> String {
+    conf.and_then(|f| f.hubs.clone())
+        .and_then(|f| f.configuration)
+        .and_then(|f| f.providers)
+        .and_then(|f| f.env)
+        .and_then(|f| f.prefix)
+        .unwrap_or(DEFAULT_ENV_PREFIX.to_string())
+}
+
+/// The configuration tree built from the environment variables of the configured prefix.
+pub fn environment_tree(conf: Option<&Configuration>) -> Value {
+    let prefix = env_prefix(conf);
+    environment::JsonEnvironmentVarsTree::new(&format!("{}__", &prefix)).build()
+}
+
 pub struct EnvironmentMiddleware;
 
 impl<S, B> Transform<S, ServiceRequest> for EnvironmentMiddleware
@@ -60,20 +77,10 @@ where
             .and_then(|f| f.remote_path)
             .unwrap_or(DEFAULT_CONF_REMOTE_PATH.to_string());
 
-        let prefix = conf
-            .and_then(|f| f.hubs.clone())
-            .and_then(|f| f.configuration)
-            .and_then(|f| f.providers)
-            .and_then(|f| f.env)
-            .and_then(|f| f.prefix)
-            .unwrap_or(DEFAULT_ENV_PREFIX.to_string());
-
         if path == conf_remote_path {
             let (req, _pl) = req.into_parts();
             Box::pin(async move {
-                let environment =
-                    environment::JsonEnvironmentVarsTree::new(&format!("{}__", &prefix));
-                let tree = environment.build();
+                let tree = environment_tree(conf.as_ref().map(|c| c.get_ref()));
                 let resp = HttpResponse::Ok().json(tree);
                 Ok(ServiceResponse::new(req, resp.map_into_boxed_body()))
             })
@@ -0,0 +1,544 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use actix_files::file_extension_to_mime;
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{StatusCode, header},
+    web::Data,
+};
+
+use crate::{
+    core::configuration::{Configuration, ErrorPageConfiguration},
+    net::{
+        html_escape,
+        http_adapter::{Hub, HubRoutes},
+        prefers_json,
+        static_hub::{StaticHub, normalize},
+        storage::Storage,
+    },
+};
+
+/// Headers describing the replaced body, dropped along with it.
+const BODY_HEADERS: [header::HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_LENGTH,
+    header::CONTENT_ENCODING,
+    header::CONTENT_RANGE,
+    header::ETAG,
+    header::LAST_MODIFIED,
+];
+
+/// The statuses an error page applies to: `404`, `5xx` or `500-504`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum StatusMatcher {
+    Exact(u16),
+    Range(u16, u16),
+}
+
+impl StatusMatcher {
+    fn parse(key: &str) -> Option<Self> {
+        let key = key.trim().to_ascii_lowercase();
+
+        let matcher = if let Some(class) = key.strip_suffix("xx") {
+            let class: u16 = class.parse().ok()?;
+            StatusMatcher::Range(class * 100, class * 100 + 99)
+        } else if let Some((from, to)) = key.split_once('-') {
+            StatusMatcher::Range(from.trim().parse().ok()?, to.trim().parse().ok()?)
+        } else {
+            StatusMatcher::Exact(key.parse().ok()?)
+        };
+
+        match matcher {
+            StatusMatcher::Exact(s) if (400..600).contains(&s) => Some(matcher),
+            StatusMatcher::Range(from, to) if from <= to && from >= 400 && to < 600 => {
+                Some(matcher)
+            }
+            _ => None,
+        }
+    }
+
+    fn matches(&self, status: u16) -> bool {
+        match self {
+            StatusMatcher::Exact(s) => *s == status,
+            StatusMatcher::Range(from, to) => (*from..=*to).contains(&status),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PageSource {
+    /// A file of the static hub, relative to its root.
+    File(String),
+    /// `{status}`, `{reason}` and `{path}` are replaced when rendered.
+    Template(String),
+}
+
+#[derive(Debug, Clone)]
+struct ErrorPage {
+    statuses: StatusMatcher,
+    source: PageSource,
+}
+
+/// Where the page files are read from: the storage of the static hub, its archive or bucket
+/// included, or its directory when it has none.
+#[derive(Debug, Clone, Default)]
+struct PageFiles {
+    storage: Option<Arc<dyn Storage>>,
+    root: PathBuf,
+}
+
+impl PageFiles {
+    fn new(hub: &StaticHub) -> Self {
+        Self {
+            storage: hub.storage.clone(),
+            root: PathBuf::from(&hub.path),
+        }
+    }
+
+    /// Whether a file exists, looked up in the tree mirroring the storage when there is one,
+    /// object stores only failing when the page is rendered.
+    fn exists(&self, path: &str) -> bool {
+        let root = match self.storage.as_ref() {
+            Some(storage) => storage.local_directory().or(storage.document_root()),
+            None => Some(self.root.as_path()),
+        };
+        root.is_none_or(|root| root.join(path).is_file())
+    }
+
+    async fn read(&self, path: &str) -> io::Result<String> {
+        match self.storage.as_ref() {
+            Some(storage) => {
+                let body = storage.read(path, None).await?;
+                String::from_utf8(body.to_vec()).map_err(io::Error::other)
+            }
+            None => tokio::fs::read_to_string(self.root.join(path)).await,
+        }
+    }
+}
+
+impl ErrorPage {
+    fn new(key: &str, conf: &ErrorPageConfiguration, files: &PageFiles) -> Result<Self, String> {
+        let statuses = StatusMatcher::parse(key).ok_or_else(|| {
+            format!("invalid error page status '{key}', expected e.g. 404, 5xx or 500-504")
+        })?;
+
+        let source = match (conf.file.as_ref(), conf.template.as_ref()) {
+            (Some(file), None) => {
+                let path = normalize(file).filter(|p| files.exists(p)).ok_or_else(|| {
+                    format!("error page file '{file}' for '{key}' does not exist")
+                })?;
+                PageSource::File(path)
+            }
+            (None, Some(template)) => PageSource::Template(template.clone()),
+            _ => {
+                return Err(format!(
+                    "error page '{key}' must have either a file or a template"
+                ));
+            }
+        };
+
+        Ok(Self { statuses, source })
+    }
+
+    /// The body and content type of the page.
+    async fn render(
+        &self,
+        files: &PageFiles,
+        status: StatusCode,
+        path: &str,
+    ) -> io::Result<(String, String)> {
+        match &self.source {
+            PageSource::File(file) => {
+                let body = files.read(file).await?;
+                let extension = Path::new(file)
+                    .extension()
+                    .and_then(|e| e.to_str())
+                    .unwrap_or("html");
+                Ok((body, file_extension_to_mime(extension).to_string()))
+            }
+            PageSource::Template(template) => {
+                let body = template
+                    .replace("{status}", status.as_str())
+                    .replace("{reason}", status.canonical_reason().unwrap_or_default())
+                    .replace("{path}", &html_escape(path));
+                Ok((body, "text/html; charset=utf-8".to_string()))
+            }
+        }
+    }
+}
+
+/// The error pages of a hub or of the global section, exact statuses taking precedence over ranges.
+fn compile_pages(
+    pages: Option<&HashMap<String, ErrorPageConfiguration>>,
+    files: &PageFiles,
+    errors: &mut Vec<String>,
+) -> Vec<ErrorPage> {
+    let mut compiled: Vec<ErrorPage> = pages
+        .into_iter()
+        .flatten()
+        .filter_map(|(key, conf)| {
+            ErrorPage::new(key, conf, files)
+                .map_err(|e| errors.push(e))
+                .ok()
+        })
+        .collect();
+
+    compiled.sort_by_key(|p| match p.statuses {
+        StatusMatcher::Exact(s) => (0, s, s),
+        StatusMatcher::Range(from, to) => (1, to - from, from),
+    });
+    compiled
+}
+
+/// The error pages of each hub, falling back to the global ones.
+#[derive(Debug, Clone, Default)]
+pub struct ErrorPages {
+    files: PageFiles,
+    global: Vec<ErrorPage>,
+    _static: Vec<ErrorPage>,
+    configuration: Vec<ErrorPage>,
+    upstream: Vec<ErrorPage>,
+}
+
+impl ErrorPages {
+    /// The pages of a configuration, whose files are read from its static hub.
+    pub fn new(configuration: &Configuration, hub: &StaticHub) -> io::Result<Self> {
+        let hubs = configuration.hubs.as_ref();
+        let files = PageFiles::new(hub);
+        let mut errors = Vec::new();
+
+        let pages = Self {
+            global: compile_pages(
+                configuration
+                    .global
+                    .as_ref()
+                    .and_then(|g| g.error_pages.as_ref()),
+                &files,
+                &mut errors,
+            ),
+            _static: compile_pages(
+                hubs.and_then(|h| h._static.as_ref())
+                    .and_then(|s| s.error_pages.as_ref()),
+                &files,
+                &mut errors,
+            ),
+            configuration: compile_pages(
+                hubs.and_then(|h| h.configuration.as_ref())
+                    .and_then(|c| c.error_pages.as_ref()),
+                &files,
+                &mut errors,
+            ),
+            upstream: compile_pages(
+                hubs.and_then(|h| h.upstream.as_ref())
+                    .and_then(|u| u.error_pages.as_ref()),
+                &files,
+                &mut errors,
+            ),
+            files,
+        };
+
+        if !errors.is_empty() {
+            errors.sort();
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid error pages:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(pages)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.global.is_empty()
+            && self._static.is_empty()
+            && self.configuration.is_empty()
+            && self.upstream.is_empty()
+    }
+
+    fn get(&self, hub: Option<Hub>, status: StatusCode) -> Option<&ErrorPage> {
+        let (hub_pages, global) = match hub {
+            Some(Hub::Static) => (self._static.as_slice(), self.global.as_slice()),
+            Some(Hub::Configuration) => (self.configuration.as_slice(), self.global.as_slice()),
+            // the error bodies of upstream APIs are only replaced by the pages of their hub
+            Some(Hub::Upstream) => (self.upstream.as_slice(), [].as_slice()),
+            None => ([].as_slice(), [].as_slice()),
+        };
+
+        hub_pages
+            .iter()
+            .chain(global)
+            .find(|p| p.statuses.matches(status.as_u16()))
+    }
+}
+
+/// Replaces the body of the error responses with the configured pages,
+/// or with a JSON document when the client accepts JSON.
+pub struct ErrorPagesMiddleware;
+
+pub struct ErrorPagesMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ErrorPagesMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ErrorPagesMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ErrorPagesMiddlewareService { service }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ErrorPagesMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let pages = req
+            .app_data::<Data<ErrorPages>>()
+            .filter(|p| !p.is_empty())
+            .cloned();
+
+        let Some(pages) = pages else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let hub = req
+            .app_data::<Data<HubRoutes>>()
+            .and_then(|r| r.hub_of(req.path()));
+        let json = prefers_json(req.headers());
+        let path = req.path().to_string();
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            // the request cannot be kept to answer the errors, as routing requires it unshared
+            let res = fut.await?.map_into_boxed_body();
+
+            let status = res.status();
+            let Some(page) = pages.get(hub, status) else {
+                return Ok(res);
+            };
+
+            let (body, content_type) = if json {
+                let body = serde_json::json!({
+                    "status": status.as_u16(),
+                    "error": status.canonical_reason().unwrap_or_default(),
+                    "path": path,
+                });
+                (body.to_string(), "application/json".to_string())
+            } else {
+                match page.render(&pages.files, status, &path).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        eprintln!("failed to render the error page for {status}: {e}");
+                        return Ok(res);
+                    }
+                }
+            };
+
+            let (req, original) = res.into_parts();
+            let mut builder = HttpResponse::build(status);
+            for (name, value) in original.headers() {
+                if !BODY_HEADERS.contains(name) {
+                    builder.append_header((name.clone(), value.clone()));
+                }
+            }
+            let res = builder
+                .insert_header((header::CONTENT_TYPE, content_type))
+                .body(body);
+
+            Ok(ServiceResponse::new(req, res))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_matcher() {
+        assert_eq!(StatusMatcher::parse("404"), Some(StatusMatcher::Exact(404)));
+        assert_eq!(
+            StatusMatcher::parse("5xx"),
+            Some(StatusMatcher::Range(500, 599))
+        );
+        assert_eq!(
+            StatusMatcher::parse("500-504"),
+            Some(StatusMatcher::Range(500, 504))
+        );
+        assert_eq!(StatusMatcher::parse("200"), None);
+        assert_eq!(StatusMatcher::parse("504-500"), None);
+        assert_eq!(StatusMatcher::parse("oops"), None);
+    }
+
+    #[test]
+    fn test_error_pages_precedence() {
+        let conf: Configuration = serde_yaml::from_str(
+            r#"
+global:
+  error_pages:
+    4xx:
+      template: "global {status}"
+hubs:
+  static:
+    error_pages:
+      4xx:
+        template: "static {status}"
+      404:
+        template: "static not found"
+"#,
+        )
+        .unwrap();
+        let pages = ErrorPages::new(&conf, &StaticHub::new(None).unwrap()).unwrap();
+
+        let page = |hub, status| match &pages.get(hub, status).unwrap().source {
+            PageSource::Template(t) => t.clone(),
+            PageSource::File(_) => unreachable!(),
+        };
+        assert_eq!(
+            page(Some(Hub::Static), StatusCode::NOT_FOUND),
+            "static not found"
+        );
+        assert_eq!(
+            page(Some(Hub::Static), StatusCode::FORBIDDEN),
+            "static {status}"
+        );
+        assert!(
+            pages
+                .get(Some(Hub::Upstream), StatusCode::NOT_FOUND)
+                .is_none()
+        );
+        assert!(pages.get(None, StatusCode::BAD_GATEWAY).is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_upstream_failure() {
+        use crate::{
+            core::rewrite::HubRules, net::reverse_proxy_middleware::ReverseProxyMiddleware,
+        };
+        use actix_web::{App, test, web};
+
+        let conf: Configuration = serde_yaml::from_str(
+            r#"
+global:
+  error_pages:
+    404:
+      template: "global {status}"
+hubs:
+  upstream:
+    target: http://127.0.0.1:1
+    remote_path: /api
+    error_pages:
+      5xx:
+        template: "upstream {status}"
+"#,
+        )
+        .unwrap();
+        let routes = HubRoutes::new(&conf, &HubRules::load(&conf).unwrap()).unwrap();
+
+        let app = test::init_service(
+            App::new()
+                .app_data(Data::new(conf.clone()))
+                .app_data(Data::new(ErrorPages::new(&conf, &routes._static).unwrap()))
+                .app_data(Data::new(routes))
+                .wrap(ErrorPagesMiddleware)
+                .service(
+                    web::scope("/api")
+                        .wrap(ReverseProxyMiddleware)
+                        .default_service(web::to(HttpResponse::NotFound)),
+                ),
+        )
+        .await;
+
+        // nothing listens on the upstream port
+        let req = test::TestRequest::get().uri("/api/users").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(test::read_body(res).await, "upstream 502");
+
+        let req = test::TestRequest::get()
+            .uri("/api/users")
+            .insert_header((header::ACCEPT, "application/json"))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let body: serde_json::Value = test::read_body_json(res).await;
+        assert_eq!(body["status"], 502);
+    }
+
+    #[actix_web::test]
+    async fn test_archive_pages() {
+        use crate::core::configuration::StaticHubConfiguration;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.tar");
+        let mut builder = tar::Builder::new(std::fs::File::create(&path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_size(9);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, "errors/404.html", &b"not found"[..])
+            .unwrap();
+        builder.into_inner().unwrap();
+
+        let conf: Configuration = serde_yaml::from_str(&format!(
+            "hubs:\n  static:\n    path: {}\n    error_pages:\n      404:\n        file: errors/404.html",
+            path.display()
+        ))
+        .unwrap();
+        let static_conf: Option<&StaticHubConfiguration> =
+            conf.hubs.as_ref().and_then(|h| h._static.as_ref());
+        let hub = StaticHub::new(static_conf)
+            .unwrap()
+            .open_storage(static_conf)
+            .unwrap();
+
+        // page files are read out of the archive
+        let pages = ErrorPages::new(&conf, &hub).unwrap();
+        let page = pages.get(Some(Hub::Static), StatusCode::NOT_FOUND).unwrap();
+        let (body, content_type) = page
+            .render(&pages.files, StatusCode::NOT_FOUND, "/missing")
+            .await
+            .unwrap();
+        assert_eq!(body, "not found");
+        assert_eq!(content_type, "text/html");
+
+        let conf: Configuration = serde_yaml::from_str(&format!(
+            "hubs:\n  static:\n    path: {}\n    error_pages:\n      404:\n        file: missing.html",
+            path.display()
+        ))
+        .unwrap();
+        assert!(ErrorPages::new(&conf, &hub).is_err());
+    }
+}
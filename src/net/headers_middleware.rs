@@ -6,6 +6,7 @@ use std::{
 };
 
 use actix_web::{
+    HttpMessage,
     body::MessageBody,
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     error::Error,
@@ -13,7 +14,13 @@ use actix_web::{
     web::Data,
 };
 
-use crate::{core::configuration::Configuration, net::DEFAULT_CONF_REMOTE_PATH};
+use crate::{
+    core::configuration::Configuration,
+    net::{
+        DEFAULT_CONF_REMOTE_PATH,
+        env_injection_middleware::{CspNonce, NONCE_PLACEHOLDER},
+    },
+};
 
 const DEFAULT_HEADER_KEY: &str = "x-unknown-header";
 const DEFAULT_HEADER_VALUE: &str = "unknown-value";
@@ -109,12 +116,18 @@ where
 
         Box::pin(async move {
             let mut res = fut.await?;
+            let nonce = res.request().extensions().get::<CspNonce>().cloned();
 
             for (key, value) in headers.unwrap_or_default() {
                 if key.trim().is_empty() || value.trim().is_empty() {
                     println!("Skipping invalid header: '{key}' -> '{value}'");
                     continue;
                 }
+                // the CSP nonce given to the injected script, when there is one
+                let value = match &nonce {
+                    Some(nonce) => value.replace(NONCE_PLACEHOLDER, &nonce.0),
+                    None => value,
+                };
                 res.headers_mut().insert(
                     HeaderName::from_str(&key)
                         .unwrap_or(HeaderName::from_static(DEFAULT_HEADER_KEY)),
@@ -0,0 +1,155 @@
+use actix_web::{
+    HttpResponse,
+    body::{self, BodySize, MessageBody},
+    dev::ServiceResponse,
+    error::Error,
+    http::{Method, StatusCode, header},
+    mime::Mime,
+};
+
+use crate::net::static_hub::is_asset;
+
+/// Larger documents, and streams of unknown size, are served as they are rather than buffered.
+const MAX_DOCUMENT_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Whether a request to a path relative to the hub may be answered with an HTML document.
+pub fn may_be_document(method: &Method, relative: &str) -> bool {
+    *method == Method::GET
+        && (!is_asset(relative) || relative.ends_with(".html") || relative.ends_with(".htm"))
+}
+
+/// Asks for a document whole and unencoded, so that it can be rewritten before compression.
+pub fn request_whole(headers: &mut header::HeaderMap) {
+    for name in [
+        header::ACCEPT_ENCODING,
+        header::RANGE,
+        header::IF_MATCH,
+        header::IF_NONE_MATCH,
+        header::IF_MODIFIED_SINCE,
+        header::IF_UNMODIFIED_SINCE,
+        header::IF_RANGE,
+    ] {
+        headers.remove(name);
+    }
+}
+
+/// Rewrites the HTML document of a successful response with `rewrite`, the response being left
+/// as it is when `rewrite` returns `None`. Documents encoded, of unknown size or too large to be
+/// buffered pass through unchanged.
+pub async fn rewrite(
+    res: ServiceResponse,
+    rewrite: impl AsyncFnOnce(&str) -> Option<String>,
+) -> Result<ServiceResponse, Error> {
+    let html = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<Mime>().ok())
+        .is_some_and(|m| m.essence_str() == "text/html");
+    let small = matches!(
+        res.response().body().size(),
+        BodySize::Sized(size) if size <= MAX_DOCUMENT_SIZE
+    );
+    if !html
+        || !small
+        || res.status() != StatusCode::OK
+        || res.headers().contains_key(header::CONTENT_ENCODING)
+    {
+        return Ok(res);
+    }
+
+    let (req, res) = res.into_parts();
+    let (mut head, body) = res.into_parts();
+    let Ok(Ok(bytes)) = body::to_bytes_limited(body, MAX_DOCUMENT_SIZE as usize).await else {
+        // a failed body cannot be served anymore
+        let res = HttpResponse::InternalServerError().finish();
+        return Ok(ServiceResponse::new(req, res));
+    };
+
+    let rewritten = match std::str::from_utf8(&bytes) {
+        Ok(document) => rewrite(document).await,
+        Err(_) => None,
+    };
+    let Some(rewritten) = rewritten else {
+        return Ok(ServiceResponse::new(
+            req,
+            head.set_body(bytes).map_into_boxed_body(),
+        ));
+    };
+
+    // the stored file does not describe the document anymore
+    let headers = head.headers_mut();
+    for name in [
+        header::ETAG,
+        header::LAST_MODIFIED,
+        header::CONTENT_LENGTH,
+        header::ACCEPT_RANGES,
+    ] {
+        headers.remove(name);
+    }
+
+    Ok(ServiceResponse::new(
+        req,
+        head.set_body(rewritten).map_into_boxed_body(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        test::{self, TestRequest},
+        web::Bytes,
+    };
+    use std::{
+        pin::Pin,
+        task::{Context, Poll},
+    };
+
+    /// A body of unknown size.
+    struct Stream(Option<Bytes>);
+
+    impl MessageBody for Stream {
+        type Error = std::convert::Infallible;
+
+        fn size(&self) -> BodySize {
+            BodySize::Stream
+        }
+
+        fn poll_next(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+            Poll::Ready(self.0.take().map(Ok))
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_rewrite() {
+        let response = |body: &'static str| {
+            HttpResponse::Ok()
+                .content_type("text/html; charset=utf-8")
+                .insert_header((header::ETAG, "\"stored\""))
+                .body(body)
+        };
+        let upper = async |document: &str| Some(document.to_uppercase());
+
+        let res = TestRequest::default().to_srv_response(response("<p>page</p>"));
+        let res = rewrite(res, upper).await.unwrap();
+        assert!(!res.headers().contains_key(header::ETAG));
+        assert_eq!(test::read_body(res).await, "<P>PAGE</P>");
+
+        let res = TestRequest::default().to_srv_response(response("<p>page</p>"));
+        let res = rewrite(res, async |_: &str| None).await.unwrap();
+        assert!(res.headers().contains_key(header::ETAG));
+        assert_eq!(test::read_body(res).await, "<p>page</p>");
+
+        // a stream may be larger than any buffer
+        let stream = Stream(Some(Bytes::from_static(b"<p>page</p>")));
+        let res = TestRequest::default()
+            .to_srv_response(HttpResponse::Ok().content_type("text/html").body(stream));
+        let res = rewrite(res, upper).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(test::read_body(res).await, "<p>page</p>");
+    }
+}
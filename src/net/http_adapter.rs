@@ -1,43 +1,75 @@
-use actix_files::Files;
 use actix_rewrite::Engine;
-use actix_web::{App, HttpServer, web};
+use actix_web::{App, HttpResponse, HttpServer, Scope, dev::Server, web};
+use std::fmt;
 
 use crate::{
-    core::configuration::Configuration,
+    core::{
+        configuration::Configuration,
+        rewrite::{HubRules, RulePatterns},
+    },
     net::{
-        environment_middleware::EnvironmentMiddleware, headers_middleware::HeadersMiddleware,
+        DEFAULT_CONF_REMOTE_PATH,
+        access_middleware::AccessMiddleware,
+        admin::{self, Admin},
+        cache_middleware::{CacheMiddleware, ServedPathMiddleware},
+        clean_urls_middleware::CleanUrlsMiddleware,
+        compression_middleware::CompressionMiddleware,
+        content_etag_middleware::ContentEtagMiddleware,
+        content_type_middleware::ContentTypeMiddleware,
+        embedded_assets,
+        env_injection_middleware::EnvInjectionMiddleware,
+        env_substitution_middleware::EnvSubstitutionMiddleware,
+        environment_middleware::EnvironmentMiddleware,
+        error_pages_middleware::ErrorPagesMiddleware,
+        headers_middleware::HeadersMiddleware,
+        image_middleware::ImageMiddleware,
+        integrity_middleware::IntegrityMiddleware,
+        live_reload::{self, LIVE_RELOAD_PATH, LiveReload, LiveReloadMiddleware},
+        locale_middleware::LocaleMiddleware,
+        precompressed_middleware::PrecompressedMiddleware,
+        redirect_middleware::RedirectMiddleware,
+        releases::{self, ReleaseMiddleware},
         reverse_proxy_middleware::ReverseProxyMiddleware,
+        static_hub::StaticHub,
+        storage,
+        virtual_hosts::{self, Site, SiteId, VirtualHostMiddleware, VirtualHosts, site_guard},
     },
 };
 
 const DEFAULT_PORT: u16 = 8080;
 const DEFAULT_HOST: &str = "localhost";
-const DEFAULT_STATIC_PATH: &str = "/var/www/html/";
-const DEFAULT_DOCUMENT: &str = "index.html";
-const DEFAULT_REMOTE_PATH: &str = "/";
 
 pub struct HttpAdapter<'a> {
     configuration: &'a Configuration,
+    /// Only set in development mode.
+    live_reload: Option<web::Data<LiveReload>>,
 }
 
 impl<'a> HttpAdapter<'a> {
     pub fn new(configuration: &'a Configuration) -> Self {
-        Self { configuration }
+        Self {
+            configuration,
+            live_reload: None,
+        }
+    }
+
+    /// Serve uncached responses, with the live reload client and its events.
+    pub fn with_live_reload(mut self, live_reload: web::Data<LiveReload>) -> Self {
+        self.live_reload = Some(live_reload);
+        self
     }
 
     pub async fn run(&self) -> Result<(), std::io::Error> {
-        let mut engine = Engine::new();
+        self.server()?.await
+    }
 
-        let rules = self
-            .configuration
-            .hubs
-            .as_ref()
-            .and_then(|h| h._static.as_ref())
-            .and_then(|s| s.rewrite_rules.clone());
-
-        if let Some(r) = rules.as_ref() {
-            engine.add_rules(r).expect("failed to process rules");
-        }
+    /// Binds the server, to be awaited or stopped through its handle.
+    pub fn server(&self) -> Result<Server, std::io::Error> {
+        // every site is validated before binding, failing with all the invalid options
+        virtual_hosts::check(self.configuration)?;
+        let default = Site::new(self.configuration)?;
+        let hosts = web::Data::new(VirtualHosts::new(self.configuration)?);
+        let admin = Admin::new(self.configuration)?;
 
         let host = self
             .configuration
@@ -53,44 +85,247 @@ impl<'a> HttpAdapter<'a> {
             .and_then(|f| f.port)
             .unwrap_or(DEFAULT_PORT);
 
-        let static_path = self
-            .configuration
-            .hubs
-            .as_ref()
-            .and_then(|h| h._static.as_ref())
-            .and_then(|s| s.path.clone())
-            .unwrap_or_else(|| DEFAULT_STATIC_PATH.to_string());
-
-        let remote_path = self
-            .configuration
-            .hubs
-            .as_ref()
-            .and_then(|h| h._static.as_ref())
-            .and_then(|c| c.remote_path.clone())
-            .unwrap_or_else(|| DEFAULT_REMOTE_PATH.to_string());
-
-        let conf = self.configuration.clone();
+        let live_reload = self.live_reload.clone();
 
-        HttpServer::new(move || {
+        Ok(HttpServer::new(move || {
             App::new()
-                .app_data(web::Data::new(conf.clone()))
-                .wrap(engine.clone().middleware())
+                .app_data(default.configuration.clone())
+                .app_data(default.redirects.clone())
+                .app_data(default.compression.clone())
+                .app_data(default.cache_rules.clone())
+                .app_data(default.error_pages.clone())
+                .app_data(default.access.clone())
+                .app_data(default.routes.clone())
+                .app_data(default.releases.clone())
+                .app_data(hosts.clone())
                 .wrap(EnvironmentMiddleware)
-                .wrap(ReverseProxyMiddleware)
+                .wrap(ErrorPagesMiddleware)
+                // wrapped after the configuration hub so that its responses are compressed too
+                .wrap(CompressionMiddleware)
+                // redirects are evaluated before any hub
+                .wrap(RedirectMiddleware)
                 .wrap(HeadersMiddleware)
-                .configure(|cfg: &mut web::ServiceConfig| config(cfg, &remote_path, &static_path))
+                // wrapped after the hub headers so that the cache rules take precedence
+                .wrap(CacheMiddleware)
+                // wrapped after the cache rules so that nothing is cached in development mode
+                .wrap(LiveReloadMiddleware)
+                // wrapped last so that every middleware reads the state of the requested site
+                .wrap(VirtualHostMiddleware)
+                .configure(|cfg: &mut web::ServiceConfig| {
+                    // registered before the hubs, which may be mounted at the root
+                    if let Some(live_reload) = &live_reload {
+                        cfg.app_data(live_reload.clone())
+                            .route(LIVE_RELOAD_PATH, web::get().to(live_reload::events));
+                    }
+                    if let Some(admin) = &admin {
+                        admin::config(cfg, admin);
+                    }
+                    for (site, state) in hosts.sites() {
+                        state.routes.configure(cfg, site);
+                    }
+                    default.routes.configure(cfg, SiteId::default())
+                })
         })
         .bind(format!("{host}:{port}"))?
-        .run()
-        .await
+        .run())
+    }
+}
+
+/// The hubs requests can be routed to.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hub {
+    Static,
+    Configuration,
+    Upstream,
+}
+
+impl Hub {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hub::Static => "static",
+            Hub::Configuration => "configuration",
+            Hub::Upstream => "upstream",
+        }
     }
 }
 
-fn config(cfg: &mut web::ServiceConfig, remote_path: &str, static_path: &str) {
+impl fmt::Display for Hub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The hub routes resolved from the configuration, shared by the server and the rewrite tester.
+#[derive(Clone)]
+pub struct HubRoutes {
+    pub _static: StaticHub,
+    pub conf_remote_path: String,
+    /// Only set when the upstream hub has both a target and a remote path.
+    pub upstream_remote_path: Option<String>,
+    pub static_engine: Engine,
+    pub upstream_engine: Engine,
+    /// The static rules that may rewrite the path of an existing file, left unhashed.
+    pub static_rewrites: RulePatterns,
+}
+
+impl HubRoutes {
+    pub fn new(configuration: &Configuration, rules: &HubRules) -> std::io::Result<Self> {
+        let hubs = configuration.hubs.as_ref();
+
+        let upstream_remote_path = hubs
+            .and_then(|h| h.upstream.as_ref())
+            .filter(|u| u.target.is_some())
+            .and_then(|u| u.remote_path.clone());
+
+        let conf_remote_path = hubs
+            .and_then(|h| h.configuration.as_ref())
+            .and_then(|c| c.remote_path.clone())
+            .unwrap_or(DEFAULT_CONF_REMOTE_PATH.to_string());
+
+        let static_conf = hubs.and_then(|h| h._static.as_ref());
+        let _static = StaticHub::new(static_conf)?.open_storage(static_conf)?;
+        // conditions looking for files are evaluated against the tree mirroring the storage
+        let document_root = _static.storage.as_ref().and_then(|s| s.document_root());
+        let static_engine = match document_root {
+            Some(document_root) => rules._static.with_document_root(document_root).engine(),
+            None => rules._static.engine(),
+        };
+        let static_rewrites = rules
+            ._static
+            .file_rewrites(_static.serves_local_directory() || document_root.is_some());
+
+        Ok(Self {
+            _static,
+            conf_remote_path,
+            upstream_remote_path,
+            static_engine,
+            upstream_engine: rules.upstream.engine(),
+            static_rewrites,
+        })
+    }
+
+    /// The hub a path is routed to, before any rewrite.
+    pub fn hub_of(&self, path: &str) -> Option<Hub> {
+        let within = |remote_path: &str| {
+            let prefix = remote_path.trim_end_matches('/');
+            path == prefix || path.starts_with(&format!("{prefix}/"))
+        };
+
+        // the configuration endpoint is matched first, as the static hub may be mounted at the root
+        if path == self.conf_remote_path {
+            return Some(Hub::Configuration);
+        }
+
+        match self.upstream_remote_path.as_deref() {
+            Some(upstream) if within(upstream) => Some(Hub::Upstream),
+            _ if within(&self._static.remote_path) => Some(Hub::Static),
+            _ => None,
+        }
+    }
+
+    /// Registers the hub scopes, only matched by the requests routed to `site`.
+    pub fn configure(&self, cfg: &mut web::ServiceConfig, site: SiteId) {
+        // the upstream scope must be registered before the static one,
+        // which may be mounted at the root and would shadow it
+        if let Some(upstream_remote_path) = self.upstream_remote_path.as_ref() {
+            upstream_config(
+                cfg,
+                upstream_remote_path,
+                self.upstream_engine.clone(),
+                site,
+            );
+        }
+        self.configure_static(cfg, site)
+    }
+
+    /// Registers the static hub scope alone, after any scope it may shadow.
+    pub fn configure_static(&self, cfg: &mut web::ServiceConfig, site: SiteId) {
+        config(cfg, &self._static, self.static_engine.clone(), site)
+    }
+}
+
+/// Scope of a hub mounted at `remote_path`.
+pub fn hub_scope(remote_path: &str) -> Scope {
+    // a trailing slash would make the scope miss every nested path, "/" included
+    web::scope(remote_path.trim_end_matches('/'))
+}
+
+fn upstream_config(cfg: &mut web::ServiceConfig, remote_path: &str, engine: Engine, site: SiteId) {
     cfg.service(
-        Files::new(remote_path, static_path)
-            .index_file(DEFAULT_DOCUMENT)
-            .use_last_modified(true)
-            .prefer_utf8(true),
+        hub_scope(remote_path)
+            .guard(site_guard(site))
+            .wrap(ReverseProxyMiddleware)
+            // wrapped last so rules are applied before the request is forwarded
+            .wrap(engine.middleware())
+            .default_service(web::to(HttpResponse::NotFound)),
     );
 }
+
+fn config(cfg: &mut web::ServiceConfig, hub: &StaticHub, engine: Engine, site: SiteId) {
+    // Wrapped from the innermost to the outermost middleware: precompressed siblings, their
+    // content type and tag, the environment substitution and injection, the derived images,
+    // the access policy and the served path on the localized path, the locale on the rewritten
+    // path, the rewrite rules, clean URLs on the requested path, the integrity attributes
+    // resolving relative URLs against it, and the release serving the whole request.
+    let scope = hub_scope(&hub.remote_path)
+        .guard(site_guard(site))
+        .app_data(web::Data::new(hub.clone()))
+        .wrap(PrecompressedMiddleware)
+        .wrap(ContentTypeMiddleware)
+        .wrap(ContentEtagMiddleware)
+        .wrap(EnvSubstitutionMiddleware)
+        .wrap(EnvInjectionMiddleware)
+        .wrap(ImageMiddleware)
+        .wrap(AccessMiddleware)
+        .wrap(ServedPathMiddleware)
+        .wrap(LocaleMiddleware)
+        .wrap(engine.middleware())
+        .wrap(CleanUrlsMiddleware)
+        .wrap(IntegrityMiddleware)
+        .wrap(ReleaseMiddleware);
+
+    cfg.service(match (hub.embedded, hub.serves_local_directory()) {
+        (true, _) => scope.default_service(embedded_assets::service(hub.clone())),
+        (false, _) if hub.releases.is_some() => scope.default_service(releases::service()),
+        (false, true) => scope.service(hub.files()),
+        (false, false) => scope.default_service(storage::service(hub.clone())),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::configuration::StaticHubConfiguration;
+    use actix_web::{http::StatusCode, test};
+
+    #[actix_web::test]
+    async fn test_hub_scopes() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::write(dir.join("app.js"), "console.log('sthub')").unwrap();
+
+        let app = test::init_service(App::new().configure(|cfg| {
+            upstream_config(cfg, "/proxy_pass", Engine::new(), SiteId::default());
+            let conf: StaticHubConfiguration =
+                serde_yaml::from_str(&format!("path: {}", dir.display())).unwrap();
+            config(
+                cfg,
+                &StaticHub::new(Some(&conf)).unwrap(),
+                Engine::new(),
+                SiteId::default(),
+            );
+        }))
+        .await;
+
+        let req = test::TestRequest::get().uri("/app.js").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // the upstream prefix is never handed over to the static hub
+        let req = test::TestRequest::get()
+            .uri("/proxy_pass/app.js")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
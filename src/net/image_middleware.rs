@@ -0,0 +1,544 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+    time::UNIX_EPOCH,
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method,
+        header::{self, HeaderValue},
+    },
+    web::{self, Bytes, Data},
+};
+use sha2::{Digest, Sha256};
+use tempfile::TempDir;
+use tokio::sync::Semaphore;
+
+use crate::{
+    core::configuration::StaticHubConfiguration,
+    net::{
+        static_hub::{StaticHub, normalize},
+        storage::{Metadata, Storage},
+    },
+};
+
+const DEFAULT_QUALITY: u8 = 80;
+/// The prefix of the default cache directory, created for each hub in the temporary directory.
+const DEFAULT_CACHE_PREFIX: &str = "sthub-images-";
+/// Hex digits of the SHA-256 naming the derived images, also used as their ETag.
+const KEY_LENGTH: usize = 32;
+
+/// The image formats read and written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+    Webp,
+    Avif,
+}
+
+impl ImageFormat {
+    const ALL: [ImageFormat; 4] = [Self::Jpeg, Self::Png, Self::Webp, Self::Avif];
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "jpeg" | "jpg" => Some(Self::Jpeg),
+            "png" => Some(Self::Png),
+            "webp" => Some(Self::Webp),
+            "avif" => Some(Self::Avif),
+            _ => None,
+        }
+    }
+
+    /// The format of a source image, by extension; AVIF images are never decoded.
+    fn of_source(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        Self::from_name(extension).filter(|f| *f != Self::Avif)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "jpeg",
+            Self::Png => "png",
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+
+    fn mime(&self) -> &'static str {
+        match self {
+            Self::Jpeg => "image/jpeg",
+            Self::Png => "image/png",
+            Self::Webp => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    /// Whether the binary was built with the encoder of the format.
+    fn available(&self) -> bool {
+        match self {
+            Self::Avif => cfg!(feature = "avif"),
+            _ => cfg!(feature = "images"),
+        }
+    }
+}
+
+/// The resized and converted images of the hub, derived on first request and stored on disk.
+#[derive(Debug)]
+pub struct Images {
+    widths: Vec<u32>,
+    formats: Vec<ImageFormat>,
+    quality: u8,
+    cache_dir: PathBuf,
+    /// The default cache directory, private to the hub and removed with it.
+    _temporary_dir: Option<TempDir>,
+    /// Bounds the images resized at once, each one keeping a thread of the blocking pool busy.
+    resizes: Semaphore,
+}
+
+impl Images {
+    /// `None` when the images are served as they are.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Option<Self>> {
+        let Some(conf) = conf.and_then(|c| c.images.as_ref()) else {
+            return Ok(None);
+        };
+
+        let mut errors = Vec::new();
+        if conf.widths.is_empty() {
+            errors.push("no allowed width".to_string());
+        }
+        if conf.widths.contains(&0) {
+            errors.push("invalid width 0".to_string());
+        }
+
+        let formats = match &conf.formats {
+            None => ImageFormat::ALL
+                .into_iter()
+                .filter(ImageFormat::available)
+                .collect(),
+            Some(names) => names
+                .iter()
+                .filter_map(|name| match ImageFormat::from_name(name) {
+                    Some(format) if format.available() => Some(format),
+                    Some(format) => {
+                        errors.push(format!(
+                            "the {} format requires sthub built with the `{}` feature",
+                            format.name(),
+                            if format == ImageFormat::Avif {
+                                "avif"
+                            } else {
+                                "images"
+                            }
+                        ));
+                        None
+                    }
+                    None => {
+                        errors.push(format!("unknown image format '{name}'"));
+                        None
+                    }
+                })
+                .collect(),
+        };
+
+        let quality = conf.quality.unwrap_or(DEFAULT_QUALITY);
+        if !(1..=100).contains(&quality) {
+            errors.push(format!("invalid quality {quality}, expected 1 to 100"));
+        }
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid images:\n{}", errors.join("\n")),
+            ));
+        }
+
+        // a shared, predictable directory would serve the files planted in it by other users
+        let (cache_dir, temporary_dir) = match &conf.cache_dir {
+            Some(dir) => (PathBuf::from(dir), None),
+            None => {
+                let mut builder = tempfile::Builder::new();
+                builder.prefix(DEFAULT_CACHE_PREFIX);
+                #[cfg(unix)]
+                builder.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+                let dir = builder.tempdir()?;
+                (dir.path().to_path_buf(), Some(dir))
+            }
+        };
+        let parallelism = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+        Ok(Some(Self {
+            widths: conf.widths.clone(),
+            formats,
+            quality,
+            cache_dir,
+            _temporary_dir: temporary_dir,
+            resizes: Semaphore::new(parallelism),
+        }))
+    }
+
+    /// The best allowed format accepted by the client, the source one otherwise.
+    fn negotiate(&self, accept: &str, source: ImageFormat) -> ImageFormat {
+        [ImageFormat::Avif, ImageFormat::Webp]
+            .into_iter()
+            .find(|f| self.formats.contains(f) && accept.contains(f.mime()))
+            .unwrap_or(source)
+    }
+
+    /// The variant of an image, derived again when the image changes, with its key.
+    async fn get(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        path: &str,
+        metadata: &Metadata,
+        width: Option<u32>,
+        format: ImageFormat,
+    ) -> std::io::Result<(Bytes, String)> {
+        let modified = metadata
+            .modified
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos());
+        let variant = format!(
+            "{}\n{}\n{modified:?}\n{:?}\n{width:?}\n{}\n{}",
+            Path::new(&hub.path).join(path).display(),
+            metadata.size,
+            metadata.etag,
+            format.name(),
+            self.quality
+        );
+        let key: String = Sha256::digest(variant.as_bytes())
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let key = key[..KEY_LENGTH].to_string();
+        let file = self.cache_dir.join(format!("{key}.{}", format.name()));
+
+        if let Ok(bytes) = tokio::fs::read(&file).await {
+            return Ok((Bytes::from(bytes), key));
+        }
+
+        let _permit = self
+            .resizes
+            .acquire()
+            .await
+            .map_err(std::io::Error::other)?;
+        // derived meanwhile by a concurrent request
+        if let Ok(bytes) = tokio::fs::read(&file).await {
+            return Ok((Bytes::from(bytes), key));
+        }
+
+        let source = storage.read(path, None).await?;
+        let quality = self.quality;
+        let derived =
+            tokio::task::spawn_blocking(move || transform(&source, width, format, quality))
+                .await
+                .map_err(std::io::Error::other)??;
+
+        // written aside then renamed, not to serve a partial image to concurrent requests
+        let mut builder = tokio::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        builder.mode(0o700);
+        builder.create(&self.cache_dir).await?;
+        let temporary = self
+            .cache_dir
+            .join(format!(".{key}.{:08x}", rand::random::<u32>()));
+        tokio::fs::write(&temporary, &derived).await?;
+        if let Err(e) = tokio::fs::rename(&temporary, &file).await {
+            let _ = tokio::fs::remove_file(&temporary).await;
+            return Err(e);
+        }
+
+        Ok((Bytes::from(derived), key))
+    }
+}
+
+/// Resizes an image down to `width`, keeping its aspect ratio, and encodes it in `format`.
+#[cfg(feature = "images")]
+fn transform(
+    source: &[u8],
+    width: Option<u32>,
+    format: ImageFormat,
+    quality: u8,
+) -> std::io::Result<Vec<u8>> {
+    use image::{
+        codecs::{jpeg::JpegEncoder, png::PngEncoder, webp::WebPEncoder},
+        imageops::FilterType,
+    };
+
+    let image = image::load_from_memory(source).map_err(std::io::Error::other)?;
+    // images are never enlarged
+    let image = match width {
+        Some(width) if width < image.width() => {
+            let height = (u64::from(image.height()) * u64::from(width) / u64::from(image.width()))
+                .max(1) as u32;
+            image.resize_exact(width, height, FilterType::Lanczos3)
+        }
+        _ => image,
+    };
+
+    let mut encoded = Vec::new();
+    let written = match format {
+        // JPEG has no alpha channel
+        ImageFormat::Jpeg => image
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality)),
+        ImageFormat::Png => image.write_with_encoder(PngEncoder::new(&mut encoded)),
+        // the WebP encoder is lossless
+        ImageFormat::Webp => image
+            .to_rgba8()
+            .write_with_encoder(WebPEncoder::new_lossless(&mut encoded)),
+        #[cfg(feature = "avif")]
+        ImageFormat::Avif => image.to_rgba8().write_with_encoder(
+            image::codecs::avif::AvifEncoder::new_with_speed_quality(&mut encoded, 8, quality),
+        ),
+        #[cfg(not(feature = "avif"))]
+        ImageFormat::Avif => return Err(std::io::ErrorKind::Unsupported.into()),
+    };
+    written.map_err(std::io::Error::other)?;
+
+    Ok(encoded)
+}
+
+#[cfg(not(feature = "images"))]
+fn transform(
+    _source: &[u8],
+    _width: Option<u32>,
+    _format: ImageFormat,
+    _quality: u8,
+) -> std::io::Result<Vec<u8>> {
+    Err(std::io::ErrorKind::Unsupported.into())
+}
+
+fn bad_request(req: ServiceRequest, message: String) -> ServiceResponse {
+    req.into_response(HttpResponse::BadRequest().body(message))
+}
+
+/// Serves resized and converted variants of the static hub images, requested with `w` and
+/// `format` query parameters, sparing the teams the thumbnails.
+pub struct ImageMiddleware;
+
+pub struct ImageMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ImageMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ImageMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ImageMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ImageMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|h| h.images.is_some() && h.storage.is_some())
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD))
+            .cloned();
+        let query = web::Query::<HashMap<String, String>>::from_query(req.query_string())
+            .map(|q| q.into_inner())
+            .unwrap_or_default();
+        let (width, format) = (query.get("w").cloned(), query.get("format").cloned());
+        let path = normalize(req.match_info().unprocessed());
+        let source = path.as_deref().and_then(ImageFormat::of_source);
+
+        let (Some(hub), Some(path), Some(source)) = (hub, path, source) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+        if width.is_none() && format.is_none() {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        }
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let (Some(images), Some(storage)) = (&hub.images, &hub.storage) else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            // only the allowed variants are derived, not to fill the disk on demand
+            let width = match width.map(|w| w.parse::<u32>()) {
+                None => None,
+                Some(Ok(width)) if images.widths.contains(&width) => Some(width),
+                Some(_) => {
+                    let allowed: Vec<String> =
+                        images.widths.iter().map(|w| w.to_string()).collect();
+                    let message =
+                        format!("width not allowed, expected one of {}", allowed.join(", "));
+                    return Ok(bad_request(req, message));
+                }
+            };
+            let negotiated = format.as_deref() == Some("auto");
+            let format = match format.as_deref() {
+                None => source,
+                Some("auto") => {
+                    let accept = req
+                        .headers()
+                        .get(header::ACCEPT)
+                        .and_then(|h| h.to_str().ok())
+                        .unwrap_or_default();
+                    images.negotiate(accept, source)
+                }
+                Some(name) => match ImageFormat::from_name(name) {
+                    Some(format) if format == source || images.formats.contains(&format) => format,
+                    _ => return Ok(bad_request(req, format!("format '{name}' not allowed"))),
+                },
+            };
+
+            let metadata = match storage.metadata(&path).await? {
+                Some(metadata) if !metadata.is_dir() => metadata,
+                // missing images are answered by the hub
+                _ => return service.call(req).await.map(|res| res.map_into_boxed_body()),
+            };
+            let (body, key) = match images
+                .get(&hub, storage.as_ref(), &path, &metadata, width, format)
+                .await
+            {
+                Ok(variant) => variant,
+                Err(e) => {
+                    eprintln!("failed to derive a variant of '{path}': {e}");
+                    let res = HttpResponse::UnprocessableEntity().finish();
+                    return Ok(req.into_response(res));
+                }
+            };
+            let etag = format!("\"{key}\"");
+
+            let not_modified = req
+                .headers()
+                .get(header::IF_NONE_MATCH)
+                .and_then(|h| h.to_str().ok())
+                .is_some_and(|h| {
+                    h.split(',')
+                        .map(|t| t.trim())
+                        .any(|t| t == "*" || t.trim_start_matches("W/") == etag)
+                });
+
+            let mut res = match not_modified {
+                true => HttpResponse::NotModified().finish(),
+                false => HttpResponse::Ok()
+                    .insert_header((header::CONTENT_TYPE, format.mime()))
+                    .body(body),
+            };
+            let headers = res.headers_mut();
+            // the key is made of hex digits
+            headers.insert(header::ETAG, HeaderValue::from_str(&etag).unwrap());
+            if negotiated {
+                headers.insert(header::VARY, HeaderValue::from_static("accept"));
+            }
+
+            Ok(req.into_response(res))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_images() {
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("images: {widths: [320, 640], formats: [webp, png]}").unwrap();
+        let images = Images::new(Some(&conf));
+        if !cfg!(feature = "images") {
+            assert!(images.is_err());
+            return;
+        }
+        let images = images.unwrap().unwrap();
+
+        let accept = "image/avif,image/webp,*/*";
+        assert_eq!(
+            images.negotiate(accept, ImageFormat::Jpeg),
+            ImageFormat::Webp
+        );
+        assert_eq!(
+            images.negotiate("*/*", ImageFormat::Jpeg),
+            ImageFormat::Jpeg
+        );
+        assert_eq!(
+            ImageFormat::of_source("photos/cat.JPG"),
+            Some(ImageFormat::Jpeg)
+        );
+        assert_eq!(ImageFormat::of_source("photos/cat.avif"), None);
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("images: {widths: [], formats: [gif], quality: 0}").unwrap();
+        let e = Images::new(Some(&conf)).unwrap_err().to_string();
+        assert_eq!(e.lines().count(), 4);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("images: {widths: [320], formats: []}").unwrap();
+        let images = Images::new(Some(&conf)).unwrap().unwrap();
+        let other = Images::new(Some(&conf)).unwrap().unwrap();
+        assert_ne!(images.cache_dir, other.cache_dir);
+        let mode = std::fs::metadata(&images.cache_dir)
+            .unwrap()
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        let cache_dir = images.cache_dir.clone();
+        drop(images);
+        assert!(!cache_dir.exists());
+    }
+
+    #[cfg(feature = "images")]
+    #[test]
+    fn test_transform() {
+        let mut source = Vec::new();
+        image::RgbImage::new(800, 600)
+            .write_with_encoder(image::codecs::png::PngEncoder::new(&mut source))
+            .unwrap();
+
+        let resized = transform(&source, Some(320), ImageFormat::Webp, 80).unwrap();
+        let resized = image::load_from_memory(&resized).unwrap();
+        assert_eq!((resized.width(), resized.height()), (320, 240));
+
+        // never enlarged
+        let kept = transform(&source, Some(1024), ImageFormat::Jpeg, 80).unwrap();
+        assert_eq!(image::load_from_memory(&kept).unwrap().width(), 800);
+    }
+}
@@ -0,0 +1,644 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    ops::Range,
+    path::Path,
+    pin::Pin,
+    rc::Rc,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, Instant, SystemTime},
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::Method,
+    web::Data,
+};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use regex::Regex;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha384};
+
+use crate::{
+    core::{
+        configuration::{Configuration, StaticHubConfiguration},
+        rewrite::RulePatterns,
+    },
+    net::{
+        access_middleware::AccessPolicy,
+        cache_middleware::glob_regex,
+        clean_urls_middleware,
+        environment_middleware::env_prefix,
+        html_document,
+        http_adapter::HubRoutes,
+        static_hub::{StaticHub, normalize},
+        storage::{Metadata, Storage, join},
+    },
+};
+
+const DEFAULT_FILES: [&str; 2] = ["*.js", "*.css"];
+const DEFAULT_MANIFEST: &str = "/integrity.json";
+/// How long a manifest is served before the storage is walked again for changed files.
+const MANIFEST_TTL: Duration = Duration::from_secs(10);
+
+/// The integrity metadata of a file, valid as long as the file is unchanged.
+#[derive(Debug, Clone)]
+struct Hashed {
+    size: u64,
+    modified: Option<SystemTime>,
+    etag: Option<String>,
+    integrity: String,
+}
+
+/// The assets of the hub hashed for subresource integrity, on first use and again when they
+/// change.
+#[derive(Debug)]
+pub struct Integrity {
+    /// Matched against the whole path when the glob has a `/`, against the file name otherwise.
+    files: Vec<(Regex, bool)>,
+    /// The normalized path of the manifest, relative to the remote path.
+    manifest: Option<String>,
+    attributes: bool,
+    tag: Regex,
+    attribute: Regex,
+    /// Keyed by the hub directory and the path, releases having files of the same name.
+    hashes: Mutex<HashMap<String, Hashed>>,
+    /// The manifests built lately, by hub directory, and when they were.
+    manifests: Mutex<HashMap<String, (Value, Instant)>>,
+}
+
+impl Integrity {
+    /// `None` when the hashes are not computed.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Option<Self>> {
+        let Some(conf) = conf.and_then(|c| c.integrity.as_ref()) else {
+            return Ok(None);
+        };
+
+        let globs = conf
+            .files
+            .clone()
+            .unwrap_or_else(|| DEFAULT_FILES.map(str::to_string).to_vec());
+        let mut errors = Vec::new();
+        let files = globs
+            .iter()
+            .filter_map(|glob| match glob_regex(glob) {
+                Ok(regex) => Some((regex, glob.contains('/'))),
+                Err(e) => {
+                    errors.push(format!("invalid integrity glob '{glob}': {e}"));
+                    None
+                }
+            })
+            .collect();
+
+        let manifest = match conf.manifest.as_deref() {
+            None => Some(DEFAULT_MANIFEST.trim_start_matches('/').to_string()),
+            Some("") => None,
+            Some(manifest) => match normalize(manifest) {
+                Some(path) if !path.is_empty() => Some(path),
+                _ => {
+                    errors.push(format!("invalid manifest path '{manifest}'"));
+                    None
+                }
+            },
+        };
+
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid integrity:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Some(Self {
+            files,
+            manifest,
+            attributes: conf.attributes.unwrap_or(true),
+            // the patterns are constant
+            tag: Regex::new(r"(?i)<(script|link)\b[^>]*>").unwrap(),
+            attribute: Regex::new(
+                r#"([^\s"'=<>/]+)(?:\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'=<>`]+)))?"#,
+            )
+            .unwrap(),
+            hashes: Mutex::new(HashMap::new()),
+            manifests: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// Whether a normalized path is hashed.
+    fn matches(&self, path: &str) -> bool {
+        let file_name = path.rsplit('/').next().unwrap_or_default();
+        self.files
+            .iter()
+            .any(|(regex, full_path)| regex.is_match(if *full_path { path } else { file_name }))
+    }
+
+    /// The attributes of a tag, without its name, names lowercased.
+    fn attributes(&self, tag: &str) -> HashMap<String, String> {
+        self.attribute
+            .captures_iter(tag)
+            .map(|c| {
+                let value = c.get(2).or(c.get(3)).or(c.get(4));
+                (
+                    c[1].to_ascii_lowercase(),
+                    value.map(|v| v.as_str().to_string()).unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    /// The tags of a document that may get an integrity attribute, with the URL they load.
+    fn candidates(&self, document: &str) -> Vec<(Range<usize>, String)> {
+        self.tag
+            .captures_iter(document)
+            .filter_map(|c| {
+                let (tag, name) = (c.get(0)?, c[1].to_ascii_lowercase());
+                let inner = tag.as_str()[1 + name.len()..].trim_end_matches(['>', '/']);
+                let attributes = self.attributes(inner);
+                if attributes.contains_key("integrity") {
+                    return None;
+                }
+
+                let url = match name.as_str() {
+                    "script" => attributes.get("src")?,
+                    _ => {
+                        let rel = attributes.get("rel")?.to_ascii_lowercase();
+                        let destination = attributes.get("as").map(|a| a.to_ascii_lowercase());
+                        // integrity is only enforced for scripts and stylesheets
+                        let checked = rel.split_ascii_whitespace().any(|r| match r {
+                            "stylesheet" | "modulepreload" => true,
+                            "preload" => matches!(destination.as_deref(), Some("script" | "style")),
+                            _ => false,
+                        });
+                        if !checked {
+                            return None;
+                        }
+                        attributes.get("href")?
+                    }
+                };
+                Some((tag.range(), url.clone()))
+            })
+            .collect()
+    }
+
+    /// Adds the integrity attributes found for their URL to the tags of a document.
+    fn add_attributes(&self, document: &str, integrity: &HashMap<String, String>) -> String {
+        let mut added = String::with_capacity(document.len());
+        let mut copied = 0;
+
+        for (range, url) in self.candidates(document) {
+            let Some(integrity) = integrity.get(&url) else {
+                continue;
+            };
+            let tag = &document[range.clone()];
+            let end = range.end - if tag.ends_with("/>") { 2 } else { 1 };
+
+            added.push_str(document[copied..end].trim_end());
+            added.push_str(&format!(" integrity=\"{integrity}\""));
+            copied = end;
+        }
+
+        added.push_str(&document[copied..]);
+        added
+    }
+
+    /// The integrity metadata of a file of the hub, as served, computed again when it changed.
+    async fn get(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        path: &str,
+        metadata: &Metadata,
+        prefix: &str,
+    ) -> std::io::Result<String> {
+        let key = Path::new(&hub.path)
+            .join(path)
+            .to_string_lossy()
+            .into_owned();
+        if let Some(cached) = self.hashes.lock().unwrap().get(&key)
+            && cached.size == metadata.size
+            && cached.modified == metadata.modified
+            && cached.etag == metadata.etag
+        {
+            return Ok(cached.integrity.clone());
+        }
+
+        // substituted files are hashed as served
+        let body = match &hub.substitute_env {
+            Some(substitution) if substitution.matches(path) => {
+                substitution
+                    .get(hub, storage, path, metadata, prefix)
+                    .await?
+                    .0
+            }
+            _ => storage.read(path, None).await?,
+        };
+        let integrity = format!("sha384-{}", STANDARD.encode(Sha384::digest(&body)));
+
+        self.hashes.lock().unwrap().insert(
+            key,
+            Hashed {
+                size: metadata.size,
+                modified: metadata.modified,
+                etag: metadata.etag.clone(),
+                integrity: integrity.clone(),
+            },
+        );
+        Ok(integrity)
+    }
+
+    /// The file served for the path of an asset relative to the remote path, as the scope serves
+    /// it: resolved by the clean URLs, localized, then looked up in the storage. `None` when the
+    /// file is not hashed, denied by the access policy or may be rewritten by the rules.
+    async fn served_asset(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        (rewrites, policy): (&RulePatterns, &AccessPolicy),
+        path: &str,
+        language: Option<&str>,
+    ) -> std::io::Result<Option<(String, Metadata)>> {
+        let mut path = path.to_string();
+        if (hub.clean_urls || hub.case_insensitive)
+            && let Some(resolved) = clean_urls_middleware::resolve(hub, storage, &path).await?
+        {
+            path = resolved;
+        }
+        if rewrites.may_rewrite(&path) {
+            return Ok(None);
+        }
+        if let (Some(locales), Some(language)) = (&hub.locales, language) {
+            path = locales.localized(hub, storage, &path, language).await?;
+        }
+
+        Ok(hub
+            .stored_file(&path)
+            .await?
+            .filter(|(served, _)| self.matches(served) && policy.permits(served)))
+    }
+
+    /// The integrity metadata of every hashed file of the hub that may be served, by URL path,
+    /// built again once `MANIFEST_TTL` elapsed.
+    async fn manifest(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        (rewrites, policy): (&RulePatterns, &AccessPolicy),
+        prefix: &str,
+    ) -> std::io::Result<Value> {
+        if let Some((manifest, at)) = self.manifests.lock().unwrap().get(&hub.path)
+            && at.elapsed() < MANIFEST_TTL
+        {
+            return Ok(manifest.clone());
+        }
+
+        let remote_path = hub.remote_path.trim_end_matches('/');
+        let mut manifest = Map::new();
+        let mut pending = vec![String::new()];
+
+        while let Some(dir) = pending.pop() {
+            for entry in storage.list(&dir).await? {
+                let path = join(&dir, &entry.name);
+                if !policy.permits(&path) {
+                    continue;
+                }
+                // served from another file, if at all
+                if !entry.metadata.is_dir() && rewrites.may_rewrite(&path) {
+                    continue;
+                }
+                if entry.metadata.is_dir() {
+                    pending.push(path);
+                } else if self.matches(&path) {
+                    let integrity = self
+                        .get(hub, storage, &path, &entry.metadata, prefix)
+                        .await?;
+                    manifest.insert(format!("{remote_path}/{path}"), Value::String(integrity));
+                }
+            }
+        }
+
+        let manifest = Value::Object(manifest);
+        self.manifests
+            .lock()
+            .unwrap()
+            .insert(hub.path.clone(), (manifest.clone(), Instant::now()));
+        Ok(manifest)
+    }
+}
+
+/// Removes the `.` and `..` segments of an absolute path.
+fn remove_dot_segments(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/').skip(1) {
+        match segment {
+            "." => {}
+            ".." => {
+                segments.pop();
+            }
+            s => segments.push(s),
+        }
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// The path of a URL referenced by a document, when of the same origin.
+fn same_origin_path(url: &str, document: &str, origin: (&str, &str)) -> Option<String> {
+    let url = url.trim().split(['?', '#']).next().unwrap_or_default();
+    let (scheme, host) = origin;
+
+    let absolute = |authority_and_path: &str| {
+        let (authority, path) = match authority_and_path.find('/') {
+            Some(i) => authority_and_path.split_at(i),
+            None => (authority_and_path, "/"),
+        };
+        authority
+            .eq_ignore_ascii_case(host)
+            .then(|| path.to_string())
+    };
+
+    let path = if let Some(rest) = url.strip_prefix("//") {
+        absolute(rest)?
+    } else if let Some((url_scheme, rest)) = url.split_once("://") {
+        if !url_scheme.eq_ignore_ascii_case(scheme) {
+            return None;
+        }
+        absolute(rest)?
+    } else if url.starts_with('/') {
+        url.to_string()
+    } else if url.is_empty() || url.split('/').next().is_some_and(|s| s.contains(':')) {
+        // `data:`, `blob:` and the like
+        return None;
+    } else {
+        let dir = &document[..document.rfind('/').map_or(0, |i| i + 1)];
+        format!("{dir}{url}")
+    };
+
+    Some(remove_dot_segments(&path))
+}
+
+/// Exposes the subresource integrity hashes of the static hub assets, in a JSON manifest and as
+/// `integrity` attributes of the tags of the HTML documents loading them.
+pub struct IntegrityMiddleware;
+
+pub struct IntegrityMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for IntegrityMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = IntegrityMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(IntegrityMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for IntegrityMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|h| h.integrity.is_some() && h.storage.is_some())
+            .cloned();
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let prefix = env_prefix(req.app_data::<Data<Configuration>>().map(|c| c.get_ref()));
+        let relative = req.match_info().unprocessed().to_string();
+        let get = matches!(*req.method(), Method::GET | Method::HEAD);
+        // assets are hashed as the scope serves them
+        let rewrites = req
+            .app_data::<Data<HubRoutes>>()
+            .map(|r| r.static_rewrites.clone())
+            .unwrap_or_default();
+        let policy = req
+            .app_data::<Data<AccessPolicy>>()
+            .map(|p| p.get_ref().clone())
+            .unwrap_or_default();
+
+        let manifest = hub
+            .integrity
+            .as_ref()
+            .and_then(|i| i.manifest.as_ref())
+            .is_some_and(|m| get && normalize(&relative).as_ref() == Some(m));
+        if manifest {
+            return Box::pin(async move {
+                let (Some(integrity), Some(storage)) = (&hub.integrity, &hub.storage) else {
+                    return Ok(req.into_response(HttpResponse::NotFound().finish()));
+                };
+                let manifest = integrity
+                    .manifest(&hub, storage.as_ref(), (&rewrites, &policy), &prefix)
+                    .await?;
+                Ok(req.into_response(HttpResponse::Ok().json(manifest)))
+            });
+        }
+
+        let document = hub.integrity.as_ref().is_some_and(|i| i.attributes)
+            && html_document::may_be_document(req.method(), &relative);
+        if !document {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        }
+
+        // the tags of the document change with the assets
+        html_document::request_whole(req.headers_mut());
+
+        let info = req.connection_info().clone();
+        let document_path = req.path().to_string();
+        // assets are served in the language of the document
+        let language = hub.locales.as_ref().map(|l| l.language(&req).to_string());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?.map_into_boxed_body();
+            let (Some(integrity), Some(storage)) = (&hub.integrity, &hub.storage) else {
+                return Ok(res);
+            };
+
+            html_document::rewrite(res, async |document: &str| {
+                let remote_path = hub.remote_path.trim_end_matches('/');
+                let mut hashes = HashMap::new();
+                for (_, url) in integrity.candidates(document) {
+                    let path = same_origin_path(&url, &document_path, (info.scheme(), info.host()))
+                        .and_then(|p| {
+                            let relative = p.strip_prefix(remote_path)?;
+                            relative.starts_with('/').then(|| normalize(relative))?
+                        })
+                        .filter(|p| integrity.matches(p));
+                    let Some(path) = path else {
+                        continue;
+                    };
+                    let served = integrity
+                        .served_asset(
+                            &hub,
+                            storage.as_ref(),
+                            (&rewrites, &policy),
+                            &path,
+                            language.as_deref(),
+                        )
+                        .await;
+                    // assets missing from the hub are left to the browser
+                    let (path, metadata) = match served {
+                        Ok(Some(served)) => served,
+                        Ok(None) => continue,
+                        Err(e) => {
+                            eprintln!("failed to look up '{path}' for integrity: {e}");
+                            continue;
+                        }
+                    };
+                    match integrity
+                        .get(&hub, storage.as_ref(), &path, &metadata, &prefix)
+                        .await
+                    {
+                        Ok(hash) => {
+                            hashes.insert(url, hash);
+                        }
+                        Err(e) => eprintln!("failed to hash '{path}' for integrity: {e}"),
+                    }
+                }
+
+                (!hashes.is_empty()).then(|| integrity.add_attributes(document, &hashes))
+            })
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{core::rewrite::RuleSet, net::storage::memory::MemoryStorage};
+    use actix_web::web::Bytes;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_add_attributes() {
+        let conf: StaticHubConfiguration = serde_yaml::from_str("integrity: {}").unwrap();
+        let integrity = Integrity::new(Some(&conf)).unwrap().unwrap();
+
+        let document = r#"<head>
+<script type="module" src="/assets/app.js"></script>
+<link rel="stylesheet" href='style.css' />
+<link rel="icon" href="/favicon.ico">
+<link rel=preload as=script href=/assets/app.js>
+<script src="/vendor.js" integrity="sha384-kept"></script>
+</head>"#;
+        let urls: Vec<String> = integrity
+            .candidates(document)
+            .into_iter()
+            .map(|(_, url)| url)
+            .collect();
+        assert_eq!(urls, ["/assets/app.js", "style.css", "/assets/app.js"]);
+
+        let hashes = HashMap::from([
+            ("/assets/app.js".to_string(), "sha384-a".to_string()),
+            ("style.css".to_string(), "sha384-s".to_string()),
+        ]);
+        let added = integrity.add_attributes(document, &hashes);
+        assert!(
+            added.contains(r#"<script type="module" src="/assets/app.js" integrity="sha384-a">"#)
+        );
+        assert!(
+            added.contains(r#"<link rel="stylesheet" href='style.css' integrity="sha384-s"/>"#)
+        );
+        assert!(
+            added.contains(
+                r#"<link rel=preload as=script href=/assets/app.js integrity="sha384-a">"#
+            )
+        );
+        assert!(added.contains(r#"<link rel="icon" href="/favicon.ico">"#));
+    }
+
+    #[actix_web::test]
+    async fn test_served_assets() {
+        let conf: StaticHubConfiguration = serde_yaml::from_str("integrity: {}").unwrap();
+        let integrity = Integrity::new(Some(&conf)).unwrap().unwrap();
+
+        let mut storage = MemoryStorage::default();
+        for path in ["app.js", "Vendor.js", "old/app.js", ".hidden.js"] {
+            storage.insert(path, Bytes::from_static(b"console.log(1)"), None);
+        }
+        let mut hub = StaticHub::new(None).unwrap();
+        hub.case_insensitive = true;
+        hub.storage = Some(Arc::new(storage));
+        let storage = hub.storage.clone().unwrap();
+        let rewrites = RuleSet::parse("rules", "RewriteRule ^/?old/ /app.js").file_rewrites(true);
+        let policy = AccessPolicy::default();
+        let scope = (&rewrites, &policy);
+
+        let served = async |path: &str| {
+            let served = integrity
+                .served_asset(&hub, storage.as_ref(), scope, path, None)
+                .await
+                .unwrap();
+            served.map(|(path, _)| path)
+        };
+        assert_eq!(served("app.js").await.as_deref(), Some("app.js"));
+        assert_eq!(served("vendor.js").await.as_deref(), Some("Vendor.js"));
+        assert_eq!(served("old/app.js").await, None);
+        assert_eq!(served(".hidden.js").await, None);
+        assert_eq!(served("missing.js").await, None);
+
+        let manifest = integrity
+            .manifest(&hub, storage.as_ref(), scope, "")
+            .await
+            .unwrap();
+        let mut urls: Vec<&String> = manifest.as_object().unwrap().keys().collect();
+        urls.sort();
+        assert_eq!(urls, ["/Vendor.js", "/app.js"]);
+
+        // served again without walking the storage
+        let empty = MemoryStorage::default();
+        let cached = integrity.manifest(&hub, &empty, scope, "").await.unwrap();
+        assert_eq!(cached, manifest);
+    }
+
+    #[test]
+    fn test_same_origin_path() {
+        let origin = ("https", "example.com");
+        let path = |url| same_origin_path(url, "/app/docs/index.html", origin);
+
+        assert_eq!(path("main.js?v=2").as_deref(), Some("/app/docs/main.js"));
+        assert_eq!(
+            path("../assets/main.js").as_deref(),
+            Some("/app/assets/main.js")
+        );
+        assert_eq!(
+            path("/assets/main.js#x").as_deref(),
+            Some("/assets/main.js")
+        );
+        assert_eq!(path("https://EXAMPLE.com/a.js").as_deref(), Some("/a.js"));
+        assert_eq!(path("//example.com/a.js").as_deref(), Some("/a.js"));
+        assert_eq!(path("http://example.com/a.js"), None);
+        assert_eq!(path("https://cdn.example.com/a.js"), None);
+        assert_eq!(path("data:text/javascript,1"), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    future::{Ready, ready},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use actix_web::{
+    HttpMessage, HttpResponse,
+    body::{BodySize, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::header::{self, HeaderValue},
+    web::{Bytes, Data},
+};
+use tokio::sync::mpsc;
+
+use crate::net::{env_injection_middleware::CspNonce, html_document};
+
+/// The server-sent events announcing the changes, outside of any hub.
+pub const LIVE_RELOAD_PATH: &str = "/__sthub/livereload";
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Comments sent to detect the closed connections, in poll intervals.
+const KEEP_ALIVE_POLLS: u32 = 30;
+
+/// Reloads the page on a `reload` event, and once the server is back after a restart.
+const CLIENT_SCRIPT: &str = r#"(() => {
+  let connected = false;
+  const events = new EventSource("/__sthub/livereload");
+  events.addEventListener("reload", () => location.reload());
+  events.onopen = () => {
+    if (connected) location.reload();
+    connected = true;
+  };
+})();"#;
+
+/// The browsers listening for changes.
+#[derive(Debug, Default)]
+pub struct LiveReload {
+    clients: Mutex<Vec<mpsc::UnboundedSender<Bytes>>>,
+}
+
+impl LiveReload {
+    fn subscribe(&self) -> EventStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        // reconnect quickly once the server restarts
+        let _ = sender.send(Bytes::from_static(b"retry: 500\n\n"));
+        self.clients.lock().unwrap().push(sender);
+        EventStream { receiver }
+    }
+
+    fn send(&self, message: Bytes) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send(message.clone()).is_ok());
+    }
+
+    /// Tells the browsers to reload, `what` having changed.
+    pub fn reload(&self, what: &str) {
+        self.send(Bytes::from(format!("event: reload\ndata: {what}\n\n")));
+    }
+}
+
+/// The events sent to a browser, until it disconnects.
+struct EventStream {
+    receiver: mpsc::UnboundedReceiver<Bytes>,
+}
+
+impl MessageBody for EventStream {
+    type Error = std::convert::Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Stream
+    }
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        self.receiver.poll_recv(cx).map(|event| event.map(Ok))
+    }
+}
+
+/// Streams the change events to a browser.
+pub async fn events(live_reload: Data<LiveReload>) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-store"))
+        .body(live_reload.subscribe())
+}
+
+/// A digest of the paths, sizes and modification times of the files under `path`.
+fn fingerprint(path: &Path) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    let mut pending = vec![path.to_path_buf()];
+
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = std::fs::metadata(&path) else {
+            continue;
+        };
+        path.hash(&mut hasher);
+        metadata.len().hash(&mut hasher);
+        metadata.modified().ok().hash(&mut hasher);
+
+        if metadata.is_dir()
+            && let Ok(entries) = std::fs::read_dir(&path)
+        {
+            let mut entries: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+            entries.sort();
+            pending.extend(entries);
+        }
+    }
+
+    hasher.finish()
+}
+
+async fn fingerprint_async(paths: &[PathBuf]) -> u64 {
+    let paths = paths.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut hasher = DefaultHasher::new();
+        for path in &paths {
+            fingerprint(path).hash(&mut hasher);
+        }
+        hasher.finish()
+    })
+    .await
+    .unwrap_or_default()
+}
+
+/// Polls the static hub directories or archives, telling the browsers to reload when one of them
+/// changes, until the configuration file changes.
+pub async fn watch(
+    static_paths: Vec<PathBuf>,
+    configuration_path: PathBuf,
+    live_reload: Data<LiveReload>,
+) {
+    let configuration_path = [configuration_path];
+    let mut files = fingerprint_async(&static_paths).await;
+    let configuration = fingerprint_async(&configuration_path).await;
+    let mut polls = 0;
+
+    loop {
+        tokio::time::sleep(POLL_INTERVAL).await;
+
+        if fingerprint_async(&configuration_path).await != configuration {
+            return;
+        }
+
+        let current = fingerprint_async(&static_paths).await;
+        if current != files {
+            files = current;
+            live_reload.reload("static");
+        }
+
+        polls += 1;
+        if polls % KEEP_ALIVE_POLLS == 0 {
+            live_reload.send(Bytes::from_static(b": keep-alive\n\n"));
+        }
+    }
+}
+
+/// Inserts the client before the end of the body, or at the end of the document.
+fn inject_client(document: &str, nonce: Option<&CspNonce>) -> String {
+    let script = match nonce {
+        Some(nonce) => format!("<script nonce=\"{}\">{CLIENT_SCRIPT}</script>", nonce.0),
+        None => format!("<script>{CLIENT_SCRIPT}</script>"),
+    };
+    let end = document
+        .to_ascii_lowercase()
+        .rfind("</body>")
+        .unwrap_or(document.len());
+
+    format!("{}{script}{}", &document[..end], &document[end..])
+}
+
+/// Serves every response uncached and unencoded in development mode, and adds the live reload
+/// client to the HTML documents.
+pub struct LiveReloadMiddleware;
+
+pub struct LiveReloadMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LiveReloadMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = LiveReloadMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LiveReloadMiddlewareService { service }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for LiveReloadMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        if req.app_data::<Data<LiveReload>>().is_none() || req.path() == LIVE_RELOAD_PATH {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        }
+
+        // the files are always served as they are on disk, whole
+        html_document::request_whole(req.headers_mut());
+
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?.map_into_boxed_body();
+
+            let headers = res.headers_mut();
+            for name in [header::ETAG, header::LAST_MODIFIED, header::EXPIRES] {
+                headers.remove(name);
+            }
+            headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+
+            let nonce = res.request().extensions().get::<CspNonce>().cloned();
+            html_document::rewrite(res, async |document: &str| {
+                Some(inject_client(document, nonce.as_ref()))
+            })
+            .await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inject_client() {
+        let injected = inject_client("<html><BODY><p>page</p></BODY></html>", None);
+        assert!(injected.starts_with("<html><BODY><p>page</p><script>"));
+        assert!(injected.ends_with("</script></BODY></html>"));
+
+        let nonce = CspNonce("abc".to_string());
+        let injected = inject_client("<p>fragment</p>", Some(&nonce));
+        assert!(injected.starts_with("<p>fragment</p><script nonce=\"abc\">"));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("assets/app.js"), "v1").unwrap();
+        let before = fingerprint(dir);
+        assert_eq!(fingerprint(dir), before);
+
+        std::fs::write(dir.join("assets/app.js"), "v2 with another size").unwrap();
+        assert_ne!(fingerprint(dir), before);
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    collections::HashMap,
+    future::{Ready, ready},
+    io,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method,
+        header::{self, HeaderMap, HeaderValue},
+    },
+    web::{self, Data},
+};
+
+use crate::{
+    core::configuration::StaticHubConfiguration,
+    net::{
+        clean_urls_middleware::rewrite_path,
+        static_hub::{StaticHub, is_asset, normalize},
+        storage::{Storage, join},
+    },
+};
+
+const DEFAULT_COOKIE: &str = "lang";
+const DEFAULT_QUERY: &str = "lang";
+
+/// The languages the static content is available in.
+#[derive(Debug, Clone)]
+pub struct Locales {
+    /// The first one is the default language.
+    languages: Vec<String>,
+    cookie: Option<String>,
+    query: Option<String>,
+}
+
+/// The variant of a path served in a language.
+#[derive(Debug, PartialEq)]
+struct Variant {
+    /// The path to serve instead of the requested one.
+    path: Option<String>,
+    /// Whether the variant depends on the language.
+    negotiated: bool,
+    /// The language of the served content, when known.
+    language: Option<String>,
+}
+
+impl Locales {
+    /// `None` when the static content is served in a single language.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> io::Result<Option<Self>> {
+        let Some(conf) = conf.and_then(|c| c.locales.as_ref()) else {
+            return Ok(None);
+        };
+
+        let mut errors = Vec::new();
+        if conf.languages.is_empty() {
+            errors.push("no language".to_string());
+        }
+        for (i, language) in conf.languages.iter().enumerate() {
+            let valid = !language.is_empty()
+                && language
+                    .split('-')
+                    .all(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric()));
+            if !valid {
+                errors.push(format!("invalid language tag '{language}'"));
+            } else if conf.languages[..i]
+                .iter()
+                .any(|l| l.eq_ignore_ascii_case(language))
+            {
+                errors.push(format!("duplicate language '{language}'"));
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid locales:\n{}", errors.join("\n")),
+            ));
+        }
+
+        let name = |name: &Option<String>, default: &str| match name.as_deref() {
+            Some("") => None,
+            Some(name) => Some(name.to_string()),
+            None => Some(default.to_string()),
+        };
+        Ok(Some(Self {
+            languages: conf.languages.clone(),
+            cookie: name(&conf.cookie, DEFAULT_COOKIE),
+            query: name(&conf.query, DEFAULT_QUERY),
+        }))
+    }
+
+    fn default_language(&self) -> &str {
+        &self.languages[0]
+    }
+
+    fn find(&self, tag: &str) -> Option<&str> {
+        self.languages
+            .iter()
+            .find(|l| l.eq_ignore_ascii_case(tag))
+            .map(String::as_str)
+    }
+
+    /// The language chosen by the user in the query or a cookie, negotiated with the
+    /// `Accept-Language` header otherwise.
+    pub fn language(&self, req: &ServiceRequest) -> &str {
+        let query = self.query.as_ref().and_then(|name| {
+            web::Query::<HashMap<String, String>>::from_query(req.query_string())
+                .ok()
+                .and_then(|q| q.get(name).cloned())
+        });
+        let cookie = self
+            .cookie
+            .as_ref()
+            .and_then(|name| req.cookie(name))
+            .map(|c| c.value().to_string());
+
+        let chosen = [query, cookie]
+            .into_iter()
+            .flatten()
+            .find_map(|tag| self.find(&tag));
+        chosen.unwrap_or_else(|| {
+            let accept = req
+                .headers()
+                .get(header::ACCEPT_LANGUAGE)
+                .and_then(|h| h.to_str().ok())
+                .unwrap_or_default();
+            self.negotiate(accept)
+        })
+    }
+
+    /// The language best matching an `Accept-Language` header, a range matching its own tag
+    /// or, failing that, a language of the same primary subtag.
+    fn negotiate(&self, accept: &str) -> &str {
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|entry| {
+                let mut params = entry.split(';');
+                let range = params.next()?.trim();
+                let q = params
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .and_then(|q| q.trim().parse().ok())
+                    .unwrap_or(1.0);
+                (!range.is_empty() && q > 0.0).then_some((range, q))
+            })
+            .collect();
+        // stable, the order of the header breaking ties
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let primary = |tag: &str| {
+            tag.split('-')
+                .next()
+                .unwrap_or_default()
+                .to_ascii_lowercase()
+        };
+        ranges
+            .iter()
+            .find_map(|(range, _)| match *range {
+                "*" => Some(self.default_language()),
+                range => self.find(range).or_else(|| {
+                    self.languages
+                        .iter()
+                        .find(|l| primary(l) == primary(range))
+                        .map(String::as_str)
+                }),
+            })
+            .unwrap_or_else(|| self.default_language())
+    }
+
+    /// The variant of `path` in `language`: the same path in the directory of the language,
+    /// or the `index.<language>.html` sibling of the document served for the path.
+    async fn variant(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        path: &str,
+        language: &str,
+    ) -> io::Result<Variant> {
+        let exists = async |path: &str| storage.metadata(path).await.map(|m| m.is_some());
+
+        // explicitly localized URLs are served as they are
+        let first = path.split('/').next().unwrap_or_default();
+        if let Some(language) = self.find(first) {
+            let is_variant = storage.metadata(first).await?.is_some_and(|m| m.is_dir());
+            return Ok(Variant {
+                path: None,
+                negotiated: false,
+                language: is_variant.then(|| language.to_string()),
+            });
+        }
+
+        // the directory of the language, when it has the target or the fallback document of
+        // application routes: other paths are served as they are, e.g. `/favicon.ico`
+        let fallback = hub
+            .fallback
+            .as_ref()
+            .filter(|_| !is_asset(path))
+            .map(|f| f.trim_start_matches('/'));
+        let directory = storage
+            .metadata(language)
+            .await?
+            .is_some_and(|m| m.is_dir())
+            && (exists(&join(language, path)).await?
+                || match fallback {
+                    Some(fallback) => exists(&join(language, fallback)).await?,
+                    None => false,
+                });
+        let root = if directory { language } else { "" };
+        let target = join(root, path);
+
+        // the document served for the target: itself, the index document of a directory, or
+        // the fallback document of the language for application routes
+        let mut served = target.clone();
+        let document = match storage.metadata(&target).await? {
+            Some(m) if m.is_dir() => {
+                let mut found = None;
+                for index in &hub.index {
+                    let index = join(&target, index);
+                    if exists(&index).await? {
+                        found = Some(index);
+                        break;
+                    }
+                }
+                found
+            }
+            Some(_) => Some(target.clone()),
+            None => match fallback {
+                Some(fallback) => {
+                    let fallback = join(root, fallback);
+                    let found = exists(&fallback).await?;
+                    if found && directory {
+                        served = fallback.clone();
+                    }
+                    found.then_some(fallback)
+                }
+                None => None,
+            },
+        };
+
+        let mut localized = directory;
+        let is_document = document.as_ref().is_some_and(|d| d.ends_with(".html"));
+        if let Some(stem) = document.as_ref().and_then(|d| d.strip_suffix(".html")) {
+            let sibling = format!("{stem}.{language}.html");
+            if exists(&sibling).await? {
+                served = sibling;
+                localized = true;
+            }
+        }
+
+        let negotiated = directory || is_document;
+        Ok(Variant {
+            path: (served != path).then_some(served),
+            negotiated,
+            // documents without variant are in the default language
+            language: negotiated.then(|| match localized {
+                true => language.to_string(),
+                false => self.default_language().to_string(),
+            }),
+        })
+    }
+
+    /// The path served for a normalized path in `language`.
+    pub async fn localized(
+        &self,
+        hub: &StaticHub,
+        storage: &dyn Storage,
+        path: &str,
+        language: &str,
+    ) -> io::Result<String> {
+        let variant = self.variant(hub, storage, path, language).await?;
+        Ok(variant.path.unwrap_or_else(|| path.to_string()))
+    }
+
+    /// Adds the headers the negotiation depends on to the `Vary` header, unless already there.
+    fn vary(&self, headers: &mut HeaderMap) {
+        let mut names = vec!["accept-language"];
+        if self.cookie.is_some() {
+            names.push("cookie");
+        }
+
+        for name in names {
+            let varies = headers.get_all(header::VARY).any(|h| {
+                h.to_str()
+                    .is_ok_and(|h| h.to_ascii_lowercase().contains(name))
+            });
+            if !varies {
+                headers.append(header::VARY, HeaderValue::from_static(name));
+            }
+        }
+    }
+}
+
+/// Serves the variant of the static content in the language of the client, out of a directory
+/// per language or `index.<language>.html` documents.
+pub struct LocaleMiddleware;
+
+pub struct LocaleMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for LocaleMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = LocaleMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(LocaleMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for LocaleMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|h| h.locales.is_some() && h.storage.is_some())
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD))
+            .cloned();
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let (Some(locales), Some(storage)) = (&hub.locales, &hub.storage) else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+            let Some(path) = normalize(req.match_info().unprocessed()) else {
+                return service.call(req).await.map(|res| res.map_into_boxed_body());
+            };
+
+            let language = locales.language(&req).to_string();
+            let variant = match locales
+                .variant(&hub, storage.as_ref(), &path, &language)
+                .await
+            {
+                Ok(variant) => variant,
+                Err(e) => {
+                    eprintln!("failed to localize '{path}': {e}");
+                    return service.call(req).await.map(|res| res.map_into_boxed_body());
+                }
+            };
+            if let Some(localized) = &variant.path
+                && let Err(e) = rewrite_path(&mut req, localized)
+            {
+                eprintln!("failed to serve '{localized}' for '{path}': {e}");
+            }
+
+            let mut res = service.call(req).await?;
+            let headers = res.headers_mut();
+            if variant.negotiated {
+                locales.vary(headers);
+            }
+            if let Some(language) = variant.language
+                && let Ok(language) = HeaderValue::from_str(&language)
+            {
+                headers.insert(header::CONTENT_LANGUAGE, language);
+            }
+
+            Ok(res.map_into_boxed_body())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::storage::memory::MemoryStorage;
+    use actix_web::web::Bytes;
+    use std::sync::Arc;
+
+    fn locales() -> Locales {
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("locales: {languages: [en, fr, pt-BR]}").unwrap();
+        Locales::new(Some(&conf)).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let locales = locales();
+        assert_eq!(locales.negotiate("fr-CA,fr;q=0.9,en;q=0.8"), "fr");
+        assert_eq!(locales.negotiate("de, en;q=0.5, fr;q=0.7"), "fr");
+        assert_eq!(locales.negotiate("pt"), "pt-BR");
+        assert_eq!(locales.negotiate("fr;q=0, de"), "en");
+        assert_eq!(locales.negotiate(""), "en");
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str("locales: {languages: [en, EN, 'f r']}").unwrap();
+        let e = Locales::new(Some(&conf)).unwrap_err().to_string();
+        assert_eq!(e.lines().count(), 3);
+    }
+
+    #[actix_web::test]
+    async fn test_variant() {
+        let mut storage = MemoryStorage::default();
+        for path in ["fr/index.html", "fr/app.js", "docs/index.en.html"] {
+            storage.insert(path, Bytes::from_static(b"variant"), None);
+        }
+        storage.insert("docs/index.html", Bytes::from_static(b"docs"), None);
+        storage.insert("favicon.ico", Bytes::from_static(b"icon"), None);
+
+        let mut hub = StaticHub::new(None).unwrap();
+        hub.fallback = Some("index.html".to_string());
+        let storage: Arc<dyn Storage> = Arc::new(storage);
+        let locales = locales();
+
+        let variant = async |path: &str, language: &str| {
+            locales
+                .variant(&hub, storage.as_ref(), path, language)
+                .await
+                .unwrap()
+        };
+        // variant directories, application routes served the fallback of the language
+        let fr = variant("settings/profile", "fr").await;
+        assert_eq!(fr.path.as_deref(), Some("fr/index.html"));
+        assert_eq!(fr.language.as_deref(), Some("fr"));
+        assert_eq!(
+            variant("app.js", "fr").await.path.as_deref(),
+            Some("fr/app.js")
+        );
+        // files missing from the directory of the language
+        let favicon = variant("favicon.ico", "fr").await;
+        assert_eq!((favicon.path, favicon.negotiated), (None, false));
+        // explicitly localized URLs
+        let fr = variant("fr/index.html", "en").await;
+        assert_eq!((fr.path, fr.negotiated), (None, false));
+        assert_eq!(fr.language.as_deref(), Some("fr"));
+        // index documents of another language
+        let docs = variant("docs", "en").await;
+        assert_eq!(docs.path.as_deref(), Some("docs/index.en.html"));
+        let docs = variant("docs/index.html", "pt-BR").await;
+        assert_eq!(docs.path, None);
+        assert_eq!(docs.language.as_deref(), Some("en"));
+    }
+}
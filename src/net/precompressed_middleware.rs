@@ -0,0 +1,153 @@
+use std::{
+    future::{Ready, ready},
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_files::NamedFile;
+use actix_web::{
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{
+        Method,
+        header::{self, ContentEncoding},
+    },
+    web::Data,
+};
+
+use crate::net::{
+    compression_middleware::{preferred_encoding, vary_accept_encoding},
+    static_hub::StaticHub,
+};
+
+/// Precompressed siblings, by order of preference when the client accepts several of them equally.
+const PRECOMPRESSED_SIBLINGS: [(ContentEncoding, &str); 3] = [
+    (ContentEncoding::Brotli, "br"),
+    (ContentEncoding::Zstd, "zst"),
+    (ContentEncoding::Gzip, "gz"),
+];
+
+/// Serves the best precompressed sibling (`app.js.br`, `app.js.zst`, `app.js.gz`) of a static file
+/// accepted by the client, with the content type of the original file.
+pub struct PrecompressedMiddleware;
+
+pub struct PrecompressedMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for PrecompressedMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = PrecompressedMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(PrecompressedMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for PrecompressedMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let hub = req.app_data::<Data<StaticHub>>().cloned();
+
+        let hub = hub
+            .filter(|h| h.precompressed)
+            .filter(|_| matches!(*req.method(), Method::GET | Method::HEAD));
+        let path = hub
+            .as_ref()
+            .and_then(|h| h.resolve(req.match_info().unprocessed()));
+
+        let (Some(hub), Some(path)) = (hub, path) else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let accept = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|h| h.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let service = self.service.clone();
+
+        Box::pin(async move {
+            let path = match tokio::fs::metadata(&path).await {
+                Ok(m) if m.is_dir() => hub.index_document(&path).await.unwrap_or(path),
+                _ => path,
+            };
+
+            let available = available_siblings(&path).await;
+
+            let encodings: Vec<ContentEncoding> = available.iter().map(|(e, _)| *e).collect();
+            let sibling = preferred_encoding(&accept, &encodings)
+                .and_then(|e| available.iter().find(|(a, _)| *a == e));
+
+            if let Some((encoding, sibling)) = sibling {
+                // the original file must exist, its content type is the one served
+                if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_file()) {
+                    let file = NamedFile::open_async(sibling)
+                        .await?
+                        .set_content_type(hub.mime_types.mime(&path.to_string_lossy()))
+                        .set_content_encoding(*encoding);
+
+                    let (req, _pl) = req.into_parts();
+                    let mut res = file.into_response(&req);
+                    vary_accept_encoding(res.headers_mut());
+                    return Ok(ServiceResponse::new(req, res));
+                }
+            }
+
+            let mut res = service.call(req).await?.map_into_boxed_body();
+            if !available.is_empty() {
+                // the response depends on the negotiation even when the original file is served
+                vary_accept_encoding(res.headers_mut());
+            }
+            Ok(res)
+        })
+    }
+}
+
+/// The precompressed siblings of a file found on disk, in order of preference.
+async fn available_siblings(path: &Path) -> Vec<(ContentEncoding, PathBuf)> {
+    let mut available = Vec::new();
+
+    for (encoding, extension) in PRECOMPRESSED_SIBLINGS {
+        let mut sibling = path.as_os_str().to_owned();
+        sibling.push(format!(".{extension}"));
+        let sibling = PathBuf::from(sibling);
+
+        if tokio::fs::metadata(&sibling)
+            .await
+            .is_ok_and(|m| m.is_file())
+        {
+            available.push((encoding, sibling));
+        }
+    }
+
+    available
+}
@@ -0,0 +1,287 @@
+use std::{
+    future::{Ready, ready},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    http::{StatusCode, header},
+    web::Data,
+};
+use regex::Regex;
+
+use crate::core::configuration::{Configuration, RedirectConfiguration, RedirectKind};
+
+const DEFAULT_REDIRECT_STATUS: u16 = 302;
+const ALLOWED_REDIRECT_STATUSES: [u16; 4] = [301, 302, 307, 308];
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+/// A redirect rule compiled from its configuration.
+#[derive(Debug, Clone)]
+pub struct Redirect {
+    matcher: Matcher,
+    to: String,
+    status: StatusCode,
+    preserve_query: bool,
+    host: Option<String>,
+}
+
+impl Redirect {
+    pub fn new(conf: &RedirectConfiguration) -> Result<Self, String> {
+        let matcher = match conf.kind.unwrap_or(RedirectKind::Exact) {
+            RedirectKind::Exact => Matcher::Exact(conf.from.clone()),
+            RedirectKind::Prefix => Matcher::Prefix(conf.from.trim_end_matches('/').to_string()),
+            RedirectKind::Regex => Matcher::Regex(
+                Regex::new(&conf.from)
+                    .map_err(|e| format!("invalid redirect pattern '{}': {e}", conf.from))?,
+            ),
+        };
+
+        let status = conf.status.unwrap_or(DEFAULT_REDIRECT_STATUS);
+        if !ALLOWED_REDIRECT_STATUSES.contains(&status) {
+            return Err(format!(
+                "invalid redirect status {status} for '{}', expected one of {ALLOWED_REDIRECT_STATUSES:?}",
+                conf.from
+            ));
+        }
+
+        Ok(Self {
+            matcher,
+            to: conf.to.clone(),
+            // checked against the allowed statuses above
+            status: StatusCode::from_u16(status).unwrap(),
+            preserve_query: conf.preserve_query.unwrap_or(true),
+            host: conf.host.as_ref().map(|h| h.to_ascii_lowercase()),
+        })
+    }
+
+    /// The redirect location for the request, if this rule applies to it.
+    pub fn location(&self, host: &str, path: &str, query: &str) -> Option<String> {
+        if !self.host.as_ref().is_none_or(|h| host_matches(h, host)) {
+            return None;
+        }
+
+        let mut location = match &self.matcher {
+            Matcher::Exact(from) => (path == from).then(|| self.to.clone())?,
+            Matcher::Prefix(from) => {
+                let rest = path.strip_prefix(from.as_str())?;
+                if !rest.is_empty() && !rest.starts_with('/') {
+                    return None;
+                }
+                let location = format!("{}{rest}", self.to.trim_end_matches('/'));
+                // a rule redirecting to the root leaves nothing of `to` for the bare prefix
+                if location.is_empty() {
+                    "/".to_string()
+                } else {
+                    location
+                }
+            }
+            Matcher::Regex(from) => {
+                let captures = from.captures(path)?;
+                let mut location = String::new();
+                captures.expand(&self.to, &mut location);
+                location
+            }
+        };
+
+        if self.preserve_query && !query.is_empty() {
+            let separator = if location.contains('?') { '&' } else { '?' };
+            location = format!("{location}{separator}{query}");
+        }
+
+        Some(location)
+    }
+}
+
+/// The redirect rules, evaluated in declaration order, first match wins.
+#[derive(Debug, Clone, Default)]
+pub struct Redirects {
+    rules: Vec<Redirect>,
+}
+
+impl Redirects {
+    pub fn new(configuration: &Configuration) -> std::io::Result<Self> {
+        let (rules, errors): (Vec<_>, Vec<_>) = configuration
+            .redirects
+            .iter()
+            .flatten()
+            .map(Redirect::new)
+            .partition(Result::is_ok);
+
+        if !errors.is_empty() {
+            let errors: Vec<String> = errors.into_iter().filter_map(Result::err).collect();
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid redirects:\n{}", errors.join("\n")),
+            ));
+        }
+
+        Ok(Self {
+            rules: rules.into_iter().filter_map(Result::ok).collect(),
+        })
+    }
+
+    /// The status and location of the first redirect matching the request.
+    pub fn resolve(&self, host: &str, path: &str, query: &str) -> Option<(StatusCode, String)> {
+        self.rules
+            .iter()
+            .find_map(|r| r.location(host, path, query).map(|l| (r.status, l)))
+    }
+}
+
+/// Whether a request host matches a lowercase host name, `*.` matching any subdomain.
+pub fn host_matches(pattern: &str, host: &str) -> bool {
+    // the port is not part of the host to match
+    let host = host
+        .split(':')
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{domain}")),
+        None => host == pattern,
+    }
+}
+
+pub struct RedirectMiddleware;
+
+pub struct RedirectMiddlewareService<S> {
+    service: S,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RedirectMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = RedirectMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RedirectMiddlewareService { service }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RedirectMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let redirect = req
+            .app_data::<Data<Redirects>>()
+            .and_then(|r| r.resolve(req.connection_info().host(), req.path(), req.query_string()));
+
+        if let Some((status, location)) = redirect {
+            let (req, _pl) = req.into_parts();
+            let resp = HttpResponse::build(status)
+                .insert_header((header::LOCATION, location))
+                .finish();
+            return Box::pin(
+                async move { Ok(ServiceResponse::new(req, resp.map_into_boxed_body())) },
+            );
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(from: &str, to: &str, kind: RedirectKind) -> RedirectConfiguration {
+        RedirectConfiguration {
+            from: from.to_string(),
+            to: to.to_string(),
+            kind: Some(kind),
+            status: None,
+            preserve_query: None,
+            host: None,
+        }
+    }
+
+    #[test]
+    fn test_redirect_kinds() {
+        let exact = Redirect::new(&redirect("/old", "/new", RedirectKind::Exact)).unwrap();
+        assert_eq!(
+            exact.location("a.com", "/old", "x=1"),
+            Some("/new?x=1".to_string())
+        );
+        assert_eq!(exact.location("a.com", "/old/page", ""), None);
+
+        let prefix = Redirect::new(&redirect("/docs/", "/guide", RedirectKind::Prefix)).unwrap();
+        assert_eq!(
+            prefix.location("a.com", "/docs/intro", ""),
+            Some("/guide/intro".to_string())
+        );
+        assert_eq!(
+            prefix.location("a.com", "/docs", ""),
+            Some("/guide".to_string())
+        );
+        assert_eq!(prefix.location("a.com", "/docsearch", ""), None);
+
+        let root = Redirect::new(&redirect("/old", "/", RedirectKind::Prefix)).unwrap();
+        assert_eq!(root.location("a.com", "/old", ""), Some("/".to_string()));
+        assert_eq!(
+            root.location("a.com", "/old", "x=1"),
+            Some("/?x=1".to_string())
+        );
+        assert_eq!(
+            root.location("a.com", "/old/page", ""),
+            Some("/page".to_string())
+        );
+
+        let regex = Redirect::new(&redirect(
+            r"^/blog/(\d+)/(?P<slug>[^/]+)$",
+            "/posts/${slug}?id=$1",
+            RedirectKind::Regex,
+        ))
+        .unwrap();
+        assert_eq!(
+            regex.location("a.com", "/blog/42/hello", "ref=home"),
+            Some("/posts/hello?id=42&ref=home".to_string())
+        );
+    }
+
+    #[test]
+    fn test_redirect_host_and_status() {
+        let mut conf = redirect("/", "https://www.a.com/", RedirectKind::Exact);
+        conf.host = Some("*.A.com".to_string());
+        conf.preserve_query = Some(false);
+        let r = Redirect::new(&conf).unwrap();
+        assert_eq!(
+            r.location("api.a.com:8080", "/", "x=1"),
+            Some("https://www.a.com/".to_string())
+        );
+        assert_eq!(r.location("a.com", "/", ""), None);
+
+        conf.status = Some(200);
+        assert!(Redirect::new(&conf).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+use std::{
+    cell::RefCell,
+    future::{Ready, ready},
+    io,
+    path::{Path, PathBuf},
+    pin::Pin,
+    rc::Rc,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use actix_files::FilesService;
+use actix_web::{
+    HttpResponse,
+    body::MessageBody,
+    dev::{
+        Extensions, Service, ServiceFactory, ServiceRequest, ServiceResponse, Transform, fn_service,
+    },
+    error::Error,
+    web::Data,
+};
+
+use crate::{
+    core::configuration::{Configuration, StaticHubConfiguration},
+    net::{archive::Archive, static_hub::StaticHub, storage::filesystem::FilesystemStorage},
+};
+
+/// The symlink to the served release.
+const CURRENT: &str = "current";
+/// The symlink to the release served before the current one, to roll back to.
+const PREVIOUS: &str = "previous";
+
+/// A directory of versioned releases, the `current` symlink pointing to the served one.
+#[derive(Debug, Clone)]
+pub struct Releases {
+    root: PathBuf,
+}
+
+impl Releases {
+    /// `None` when the static hub serves a single directory.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> Option<Self> {
+        conf.and_then(|c| c.releases.as_ref()).map(|root| Self {
+            root: PathBuf::from(root),
+        })
+    }
+
+    /// The path of the current release, through its symlink.
+    pub fn current_path(&self) -> PathBuf {
+        self.root.join(CURRENT)
+    }
+
+    /// The names of the releases, sorted.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let mut releases = Vec::new();
+
+        for entry in std::fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            // the pointers are symlinks, and the pointers being switched are hidden
+            if !name.starts_with('.') && entry.file_type()?.is_dir() {
+                releases.push(name);
+            }
+        }
+
+        releases.sort();
+        Ok(releases)
+    }
+
+    fn pointer(&self, pointer: &str) -> Option<String> {
+        let target = std::fs::read_link(self.root.join(pointer)).ok()?;
+        target.file_name()?.to_str().map(str::to_string)
+    }
+
+    pub fn current(&self) -> Option<String> {
+        self.pointer(CURRENT)
+    }
+
+    pub fn previous(&self) -> Option<String> {
+        self.pointer(PREVIOUS)
+    }
+
+    /// Serves `release` from now on, the current one becoming the previous one.
+    pub fn switch(&self, release: &str) -> io::Result<()> {
+        let valid = !release.is_empty()
+            && !release.starts_with('.')
+            && !release.contains(['/', '\\'])
+            && release != CURRENT
+            && release != PREVIOUS;
+        if !valid {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid release name '{release}'"),
+            ));
+        }
+
+        let is_release =
+            std::fs::symlink_metadata(self.root.join(release)).is_ok_and(|m| m.is_dir());
+        if !is_release {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no release '{release}' in '{}'", self.root.display()),
+            ));
+        }
+
+        match self.current() {
+            Some(current) if current == release => return Ok(()),
+            Some(current) => self.point(PREVIOUS, &current)?,
+            None => {}
+        }
+        self.point(CURRENT, release)
+    }
+
+    /// Serves the previous release again, returning its name.
+    pub fn rollback(&self) -> io::Result<String> {
+        let previous = self.previous().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "no previous release to roll back to",
+            )
+        })?;
+        self.switch(&previous)?;
+        Ok(previous)
+    }
+
+    /// Points `pointer` to `release`, a symlink renamed over the pointer replacing it atomically.
+    #[cfg(unix)]
+    fn point(&self, pointer: &str, release: &str) -> io::Result<()> {
+        let suffix: u32 = rand::random();
+        let temporary = self.root.join(format!(".{pointer}.{suffix:08x}"));
+
+        std::os::unix::fs::symlink(release, &temporary)?;
+        std::fs::rename(&temporary, self.root.join(pointer)).inspect_err(|_| {
+            let _ = std::fs::remove_file(&temporary);
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn point(&self, _pointer: &str, _release: &str) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "switching releases requires symlinks",
+        ))
+    }
+
+    /// The directory of the current release, resolved once for a whole request.
+    async fn resolve(&self) -> io::Result<PathBuf> {
+        tokio::fs::canonicalize(self.current_path()).await
+    }
+}
+
+/// Fails when the releases directory is missing, or combined with another source of files.
+pub fn check(configuration: &Configuration) -> io::Result<()> {
+    let conf = configuration.hubs.as_ref().and_then(|h| h._static.as_ref());
+    let Some(root) = conf.and_then(|c| c.releases.as_ref()) else {
+        return Ok(());
+    };
+
+    let mut errors = Vec::new();
+    if conf.and_then(|c| c.embedded).unwrap_or(false) {
+        errors.push("releases cannot be combined with embedded assets".to_string());
+    }
+    if conf.and_then(|c| c.storage.as_ref()).is_some() {
+        errors.push("releases cannot be combined with a storage".to_string());
+    }
+    if Archive::is_archive(root) {
+        errors.push(format!("'{root}' is an archive, not a releases directory"));
+    } else if !Path::new(root).is_dir() {
+        errors.push(format!("'{root}' is not a directory"));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid releases:\n{}", errors.join("\n")),
+        ))
+    }
+}
+
+/// The files service of the last release served by the worker, with its directory.
+type CachedFiles = Rc<RefCell<Option<(String, Rc<FilesService>)>>>;
+
+/// Pins the release current when the request comes in, the middlewares and the files service
+/// reading the hub of that release until the response is sent, whatever the switches meanwhile.
+pub struct ReleaseMiddleware;
+
+pub struct ReleaseMiddlewareService<S> {
+    service: Rc<S>,
+    /// The hub of the last release served by the worker.
+    pinned: Rc<RefCell<Option<Data<StaticHub>>>>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ReleaseMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = ReleaseMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ReleaseMiddlewareService {
+            service: Rc::new(service),
+            pinned: Rc::default(),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ReleaseMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hub = req
+            .app_data::<Data<StaticHub>>()
+            .filter(|h| h.releases.is_some())
+            .cloned();
+
+        let Some(hub) = hub else {
+            let fut = self.service.call(req);
+            return Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) });
+        };
+
+        let service = self.service.clone();
+        let pinned = self.pinned.clone();
+
+        Box::pin(async move {
+            let resolved = match &hub.releases {
+                Some(releases) => releases.resolve().await,
+                None => Err(io::ErrorKind::NotFound.into()),
+            };
+            let Ok(root) = resolved else {
+                // nothing is served until a first release is switched to
+                let res = HttpResponse::ServiceUnavailable().finish();
+                return Ok(req.into_response(res));
+            };
+
+            let cached = pinned
+                .borrow()
+                .clone()
+                .filter(|h| Path::new(&h.path) == root);
+            let release = cached.unwrap_or_else(|| {
+                let mut release = hub.as_ref().clone();
+                release.path = root.to_string_lossy().into_owned();
+                release.storage = Some(Arc::new(FilesystemStorage::new(&root)));
+                let release = Data::new(release);
+                *pinned.borrow_mut() = Some(release.clone());
+                release
+            });
+
+            // shadows the hub of the scope for the rest of the request
+            let mut data = Extensions::new();
+            data.insert(release);
+            req.add_data_container(Rc::new(data));
+
+            service.call(req).await.map(|res| res.map_into_boxed_body())
+        })
+    }
+}
+
+/// Serves the files of the release pinned by the `ReleaseMiddleware`.
+pub fn service() -> impl ServiceFactory<
+    ServiceRequest,
+    Config = (),
+    Response = ServiceResponse,
+    Error = Error,
+    InitError = (),
+> {
+    // `actix_files` resolving its directory once
+    let files: CachedFiles = Rc::default();
+
+    fn_service(move |req: ServiceRequest| {
+        let files = files.clone();
+
+        async move {
+            let Some(hub) = req.app_data::<Data<StaticHub>>().cloned() else {
+                return Ok(req.into_response(HttpResponse::NotFound().finish()));
+            };
+
+            let cached = files
+                .borrow()
+                .as_ref()
+                .filter(|(root, _)| *root == hub.path)
+                .map(|(_, service)| service.clone());
+            let service = match cached {
+                Some(service) => service,
+                None => {
+                    let service = hub
+                        .files()
+                        .new_service(())
+                        .await
+                        .map(Rc::new)
+                        .map_err(|_| io::Error::other("cannot serve the release"))?;
+                    *files.borrow_mut() = Some((hub.path.clone(), service.clone()));
+                    service
+                }
+            };
+
+            service.call(req).await
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_switch() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        for release in ["v1", "v2"] {
+            std::fs::create_dir_all(dir.join(release)).unwrap();
+        }
+        let releases = Releases {
+            root: dir.to_path_buf(),
+        };
+
+        assert_eq!(releases.current(), None);
+        assert!(releases.rollback().is_err());
+
+        releases.switch("v1").unwrap();
+        releases.switch("v2").unwrap();
+        assert_eq!(releases.current().as_deref(), Some("v2"));
+        assert_eq!(releases.previous().as_deref(), Some("v1"));
+        assert!(dir.join("current").canonicalize().unwrap().ends_with("v2"));
+        // the pointers are not listed as releases
+        assert_eq!(releases.list().unwrap(), ["v1", "v2"]);
+
+        assert_eq!(releases.rollback().unwrap(), "v1");
+        assert_eq!(releases.current().as_deref(), Some("v1"));
+        assert_eq!(releases.previous().as_deref(), Some("v2"));
+
+        assert!(releases.switch("v3").is_err());
+        assert!(releases.switch("../v2").is_err());
+        assert!(releases.switch("current").is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use actix_web::{
+    HttpRequest, HttpResponse,
     dev::{Service, ServiceRequest, ServiceResponse, Transform},
     web::Data,
 };
@@ -119,10 +120,14 @@ where
         }
 
         Box::pin(async move {
-            let mut res = forward_req
-                .send()
-                .await
-                .map_err(actix_web::error::ErrorBadGateway)?;
+            // answered rather than failed, for the error pages to replace it
+            let mut res = match forward_req.send().await {
+                Ok(res) => res,
+                Err(e) => {
+                    eprintln!("failed to reach the upstream: {e}");
+                    return Ok(bad_gateway(http_req));
+                }
+            };
 
             let mut client_resp = actix_web::HttpResponse::build(res.status());
 
@@ -134,8 +139,14 @@ where
                 client_resp.append_header((header_name.clone(), header_value.clone()));
             }
 
-            let body = res.body().limit(10_485_760).await; // Limit to 10MB
-            let body = body.map_err(actix_web::error::ErrorBadGateway)?;
+            let body = match res.body().limit(10_485_760).await {
+                // Limit to 10MB
+                Ok(body) => body,
+                Err(e) => {
+                    eprintln!("failed to read the upstream response: {e}");
+                    return Ok(bad_gateway(http_req));
+                }
+            };
             Ok(ServiceResponse::new(
                 http_req,
                 client_resp.body(body).map_into_boxed_body(),
@@ -143,3 +154,7 @@ where
         })
     }
 }
+
+fn bad_gateway(req: HttpRequest) -> ServiceResponse {
+    ServiceResponse::new(req, HttpResponse::BadGateway().finish())
+}
@@ -0,0 +1,390 @@
+use actix_web::{
+    App, HttpRequest, HttpResponse,
+    dev::ServiceResponse,
+    http::{StatusCode, Uri, header},
+    test,
+    web::{self, Data},
+};
+use percent_encoding::percent_decode_str;
+use std::fmt;
+
+use crate::{
+    core::{configuration::Configuration, rewrite::HubRules},
+    net::{
+        http_adapter::{Hub, HubRoutes, hub_scope},
+        static_hub::StaticHub,
+        virtual_hosts::{self, Site, SiteId},
+    },
+};
+
+/// Header set by the upstream probe on the requests it received.
+const PROBE_HUB_HEADER: &str = "x-sthub-probe-hub";
+/// Target of the probe rules telling whether the engine got to a condition and it matched.
+const CONDITION_MATCHED_PATH: &str = "/.sthub-condition-matched";
+
+/// A rewrite condition of the hub serving the request, as evaluated by the engine.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConditionTrace {
+    pub location: String,
+    pub condition: String,
+    pub matched: bool,
+}
+
+/// How a URL goes through the rewrite rules and which hub serves it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RewriteTrace {
+    pub url: String,
+    pub hub: Option<Hub>,
+    pub conditions: Vec<ConditionTrace>,
+    /// The rewritten path and query, when the request reaches a hub.
+    pub rewritten: Option<String>,
+    /// The file of the static hub served, relative to its root.
+    pub file: Option<String>,
+    /// The status answered by the rules or the hub policies (redirects, forbidden, ...).
+    pub status: Option<StatusCode>,
+    pub location: Option<String>,
+}
+
+impl fmt::Display for RewriteTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.url)?;
+
+        for c in &self.conditions {
+            let outcome = if c.matched { "matched" } else { "not matched" };
+            writeln!(f, "  condition {} {} => {outcome}", c.location, c.condition)?;
+        }
+
+        if let Some(status) = self.status {
+            match self.location.as_ref() {
+                Some(location) => writeln!(f, "  answered {status} -> {location}")?,
+                None => writeln!(f, "  answered {status}")?,
+            }
+        }
+
+        if let Some(rewritten) = self.rewritten.as_ref() {
+            writeln!(f, "  rewritten {rewritten}")?;
+        }
+
+        if let Some(file) = self.file.as_ref() {
+            writeln!(f, "  file {file}")?;
+        }
+
+        match self.hub {
+            Some(hub) => writeln!(f, "  served by {hub} hub"),
+            None => writeln!(f, "  not served by any hub"),
+        }
+    }
+}
+
+/// Replays URLs through the same redirects and hub scopes as the `HttpAdapter`, the static hub
+/// middlewares and files included, with the upstream hub replaced by a probe reporting what
+/// reached it. Each URL goes to the site of its host, like on the server.
+pub struct RewriteTester {
+    default: TestedSite,
+    sites: Vec<(Vec<String>, TestedSite)>,
+    /// The host of the requests, rather than the one of each URL.
+    host: Option<String>,
+}
+
+impl RewriteTester {
+    pub fn new(configuration: &Configuration) -> std::io::Result<Self> {
+        let mut sites = Vec::new();
+        for conf in configuration.sites.iter().flatten() {
+            let site = TestedSite::new(&configuration.site(conf)).map_err(|e| {
+                std::io::Error::new(e.kind(), format!("site {}: {e}", conf.hosts.join(", ")))
+            })?;
+            sites.push((conf.hosts.clone(), site));
+        }
+
+        Ok(Self {
+            default: TestedSite::new(configuration)?,
+            sites,
+            host: None,
+        })
+    }
+
+    /// Sends every request to `host`, selecting its site, whatever the host of the URL.
+    pub fn with_host(mut self, host: Option<&str>) -> Self {
+        self.host = host.map(str::to_string);
+        self
+    }
+
+    pub async fn trace(&self, url: &str) -> RewriteTrace {
+        let uri = url.parse::<Uri>();
+        let host = match &self.host {
+            Some(host) => Some(host.clone()),
+            None => uri
+                .as_ref()
+                .ok()
+                .and_then(|u| u.authority())
+                .map(|a| a.to_string()),
+        };
+
+        let site = host.as_deref().and_then(|host| {
+            virtual_hosts::resolve(self.sites.iter().map(|(hosts, _)| hosts.as_slice()), host)
+        });
+        let site = match site {
+            Some(i) => &self.sites[i].1,
+            None => &self.default,
+        };
+        site.trace(url, host.as_deref()).await
+    }
+}
+
+/// The state of a site the URLs are replayed through.
+struct TestedSite {
+    site: Site,
+    rules: HubRules,
+}
+
+impl TestedSite {
+    fn new(configuration: &Configuration) -> std::io::Result<Self> {
+        let site = Site::new(configuration)?;
+        let mut rules = HubRules::load_validated(configuration)?;
+
+        // conditions are probed as evaluated by the engine, against the tree mirroring the storage
+        if let Some(document_root) = site
+            .routes
+            ._static
+            .storage
+            .as_ref()
+            .and_then(|s| s.document_root())
+        {
+            rules._static = rules._static.with_document_root(document_root);
+        }
+
+        Ok(Self { site, rules })
+    }
+
+    /// Serves a request to `url` out of `routes`, with the app data of the site.
+    async fn serve(&self, routes: &HubRoutes, url: &str, host: Option<&str>) -> ServiceResponse {
+        let site = self.site.clone();
+        let routes = routes.clone();
+        let app = test::init_service(
+            App::new()
+                .app_data(site.configuration)
+                .app_data(site.routes)
+                .app_data(site.redirects)
+                .app_data(site.compression)
+                .app_data(site.cache_rules)
+                .app_data(site.error_pages)
+                .app_data(site.access)
+                .configure(|cfg| probe_config(cfg, &routes)),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri(url);
+        if let Some(host) = host {
+            req = req.insert_header((header::HOST, host));
+        }
+        test::call_service(&app, req.to_request()).await
+    }
+
+    async fn trace(&self, url: &str, host: Option<&str>) -> RewriteTrace {
+        let mut trace = RewriteTrace {
+            url: url.to_string(),
+            hub: None,
+            conditions: Vec::new(),
+            rewritten: None,
+            file: None,
+            status: None,
+            location: None,
+        };
+        let routes = &self.site.routes;
+
+        let uri = url.parse::<Uri>();
+        let path = uri.as_ref().map(|u| u.path().to_string());
+
+        // redirects are evaluated before any hub
+        if let Ok(uri) = uri.as_ref() {
+            let query = uri.query().unwrap_or_default();
+            let redirect = self
+                .site
+                .redirects
+                .resolve(host.unwrap_or_default(), uri.path(), query);
+            if let Some((status, location)) = redirect {
+                trace.status = Some(status);
+                trace.location = Some(location);
+                return trace;
+            }
+        }
+
+        // the configuration hub answers before any rewrite happens
+        if path.as_ref().is_ok_and(|p| *p == routes.conf_remote_path) {
+            trace.hub = Some(Hub::Configuration);
+            return trace;
+        }
+
+        let res = self.serve(routes, url, host).await;
+        let req = res.request();
+        let served = res.status().is_success();
+        // the scope of the static hub, or of the release it serves, leaves its hub in the app data
+        let hub = req.app_data::<Data<StaticHub>>().cloned();
+
+        trace.hub = match res.headers().get(PROBE_HUB_HEADER) {
+            Some(_) => Some(Hub::Upstream),
+            None => hub.as_ref().map(|_| Hub::Static),
+        };
+        if trace.hub.is_none() || !served {
+            trace.status = Some(res.status());
+            trace.location = res
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|h| h.to_str().ok())
+                .map(|h| h.to_string());
+        }
+
+        if let Some(hub) = hub.filter(|_| trace.hub == Some(Hub::Static)) {
+            trace.rewritten = Some(req.uri().to_string());
+
+            let path = percent_decode_str(req.path()).decode_utf8_lossy();
+            let relative = path
+                .strip_prefix(hub.remote_path.trim_end_matches('/'))
+                .map(str::to_string);
+            if let Some(relative) = relative.filter(|_| served) {
+                trace.file = hub.served_path(&relative).await.map(|p| format!("/{p}"));
+            }
+        }
+
+        if trace.hub == Some(Hub::Upstream) {
+            let body = test::read_body(res).await;
+            trace.rewritten = Some(String::from_utf8_lossy(&body).to_string());
+        }
+
+        // the hub may have been reached after a redirect or a 404, match it from the original path
+        let hub = routes.hub_of(path.as_deref().unwrap_or("/"));
+        let rules = match hub {
+            Some(Hub::Upstream) => &self.rules.upstream,
+            Some(Hub::Static) => &self.rules._static,
+            _ => return trace,
+        };
+
+        for (location, condition, probe) in rules.condition_probes(CONDITION_MATCHED_PATH) {
+            let mut routes = routes.get_ref().clone();
+            match hub {
+                Some(Hub::Upstream) => routes.upstream_engine = probe.engine(),
+                _ => routes.static_engine = probe.engine(),
+            }
+
+            let res = self.serve(&routes, url, host).await;
+            trace.conditions.push(ConditionTrace {
+                location,
+                condition: condition.to_string(),
+                matched: res.request().path().ends_with(CONDITION_MATCHED_PATH),
+            });
+        }
+
+        trace
+    }
+}
+
+/// Registers the hub scopes like `HubRoutes::configure`, with a probe instead of the upstream hub.
+fn probe_config(cfg: &mut web::ServiceConfig, routes: &HubRoutes) {
+    if let Some(upstream_remote_path) = routes.upstream_remote_path.as_ref() {
+        cfg.service(
+            hub_scope(upstream_remote_path)
+                .wrap(routes.upstream_engine.clone().middleware())
+                .default_service(web::to(probe)),
+        );
+    }
+    routes.configure_static(cfg, SiteId::default());
+}
+
+async fn probe(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header((PROBE_HUB_HEADER, Hub::Upstream.name()))
+        .body(req.uri().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn configuration(yaml: &str) -> Configuration {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[actix_web::test]
+    async fn test_trace_hubs() {
+        let tester = RewriteTester::new(&configuration(
+            r#"
+hubs:
+  static:
+    remote_path: /
+  upstream:
+    target: http://127.0.0.1:8787
+    remote_path: /proxy_pass
+"#,
+        ))
+        .unwrap();
+
+        let trace = tester.trace("/app.js?v=1").await;
+        assert_eq!(trace.hub, Some(Hub::Static));
+        assert_eq!(trace.rewritten.as_deref(), Some("/app.js?v=1"));
+
+        let trace = tester.trace("/proxy_pass/api").await;
+        assert_eq!(trace.hub, Some(Hub::Upstream));
+
+        let trace = tester.trace("/env").await;
+        assert_eq!(trace.hub, Some(Hub::Configuration));
+        assert!(trace.rewritten.is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_trace_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.path().join("about.html"), "<html></html>").unwrap();
+        std::fs::write(dir.path().join("docs/index.html"), "<html></html>").unwrap();
+        std::fs::write(dir.path().join(".env"), "SECRET=1").unwrap();
+
+        let tester = RewriteTester::new(&configuration(&format!(
+            "hubs:\n  static:\n    path: {}\n    spa: true\n    clean_urls: true",
+            dir.path().display()
+        )))
+        .unwrap();
+
+        let trace = |url: &'static str| async { tester.trace(url).await };
+        assert_eq!(
+            trace("/docs/").await.file.as_deref(),
+            Some("/docs/index.html")
+        );
+        assert_eq!(trace("/about").await.file.as_deref(), Some("/about.html"));
+        let route = trace("/settings/profile").await;
+        assert_eq!(route.hub, Some(Hub::Static));
+        assert_eq!(route.file.as_deref(), Some("/index.html"));
+
+        // answered by the access policy rather than served
+        let denied = trace("/.env").await;
+        assert_eq!(denied.status, Some(StatusCode::NOT_FOUND));
+        assert_eq!(denied.file, None);
+    }
+
+    #[actix_web::test]
+    async fn test_trace_sites() {
+        let configuration = configuration(
+            r#"
+hubs:
+  static:
+    remote_path: /
+sites:
+  - hosts: [example.com]
+    hubs:
+      upstream:
+        target: http://127.0.0.1:8787
+        remote_path: /api
+"#,
+        );
+        let tester = RewriteTester::new(&configuration).unwrap();
+        let hub = async |tester: &RewriteTester, url| tester.trace(url).await.hub;
+        assert_eq!(hub(&tester, "/api/users").await, Some(Hub::Static));
+        assert_eq!(
+            hub(&tester, "http://example.com:8080/api/users").await,
+            Some(Hub::Upstream)
+        );
+
+        let tester = tester.with_host(Some("example.com"));
+        assert_eq!(hub(&tester, "/api/users").await, Some(Hub::Upstream));
+    }
+}
@@ -0,0 +1,467 @@
+use actix_files::{Directory, Files, NamedFile};
+use actix_web::{
+    HttpRequest, HttpResponse,
+    dev::{ServiceRequest, ServiceResponse, fn_service},
+};
+use percent_encoding::percent_decode_str;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    core::configuration::{StaticHubConfiguration, StorageKind, TrailingSlash},
+    net::{
+        archive::Archive,
+        content_etag_middleware::ContentHashes,
+        content_type_middleware::MimeTypes,
+        directory_listing::DirectoryListing,
+        embedded_assets,
+        env_injection_middleware::EnvInjection,
+        env_substitution_middleware::EnvSubstitution,
+        image_middleware::Images,
+        integrity_middleware::Integrity,
+        locale_middleware::Locales,
+        releases::Releases,
+        storage::{self, Metadata, Storage, join},
+    },
+};
+
+const DEFAULT_STATIC_PATH: &str = "/var/www/html/";
+const DEFAULT_DOCUMENT: &str = "index.html";
+const DEFAULT_REMOTE_PATH: &str = "/";
+
+/// The static hub settings, resolved from its configuration.
+#[derive(Debug, Clone)]
+pub struct StaticHub {
+    pub remote_path: String,
+    pub path: String,
+    /// The document served for application routes, when the SPA fallback is enabled.
+    pub fallback: Option<String>,
+    /// Serve precompressed siblings of the requested files when accepted by the client.
+    pub precompressed: bool,
+    /// Shared by every worker, only set when content hash ETags are enabled.
+    pub content_hashes: Option<Arc<ContentHashes>>,
+    /// The directories listed when they have no index document.
+    pub listing: Option<DirectoryListing>,
+    /// Serve the assets bundled into the binary instead of `path`.
+    pub embedded: bool,
+    /// Where the files are read from, once opened.
+    pub storage: Option<Arc<dyn Storage>>,
+    /// Serve `about.html` for `/about`.
+    pub clean_urls: bool,
+    pub trailing_slash: TrailingSlash,
+    pub case_insensitive: bool,
+    /// The index documents of directories, by order of preference.
+    pub index: Vec<String>,
+    pub mime_types: MimeTypes,
+    /// Injects the environment configuration tree into the HTML documents, when enabled.
+    pub inject_env: Option<EnvInjection>,
+    /// Shared by every worker with its cache, only set when the substitution is enabled.
+    pub substitute_env: Option<Arc<EnvSubstitution>>,
+    /// The releases directory, `path` being the current release pinned for each request.
+    pub releases: Option<Releases>,
+    /// Shared by every worker with its hashes, only set when subresource integrity is enabled.
+    pub integrity: Option<Arc<Integrity>>,
+    /// Resizes and converts the images on demand, when enabled.
+    pub images: Option<Arc<Images>>,
+    /// The languages of the content variants, when negotiated.
+    pub locales: Option<Locales>,
+}
+
+impl StaticHub {
+    /// The hub of a configuration, failing with every invalid option.
+    pub fn new(conf: Option<&StaticHubConfiguration>) -> std::io::Result<Self> {
+        let releases = Releases::new(conf);
+        let path = match &releases {
+            Some(releases) => releases.current_path().to_string_lossy().into_owned(),
+            None => conf
+                .and_then(|s| s.path.clone())
+                .unwrap_or_else(|| DEFAULT_STATIC_PATH.to_string()),
+        };
+
+        let remote_path = conf
+            .and_then(|c| c.remote_path.clone())
+            .unwrap_or_else(|| DEFAULT_REMOTE_PATH.to_string());
+
+        let index = conf
+            .and_then(|c| c.index.clone())
+            .unwrap_or_else(|| vec![DEFAULT_DOCUMENT.to_string()]);
+
+        // an explicit fallback document implies the SPA mode
+        let fallback = conf.and_then(|c| match (c.spa, c.fallback.clone()) {
+            (Some(false), _) => None,
+            (_, Some(fallback)) => Some(fallback),
+            (Some(true), None) => Some(
+                index
+                    .first()
+                    .cloned()
+                    .unwrap_or_else(|| DEFAULT_DOCUMENT.to_string()),
+            ),
+            (None, None) => None,
+        });
+
+        let embedded = conf.and_then(|c| c.embedded).unwrap_or(false);
+        let object_store = conf
+            .and_then(|c| c.storage.as_ref())
+            .is_some_and(|s| s.kind == StorageKind::S3);
+        // options looking for files on disk do not apply to embedded assets, archives and
+        // object stores, which come with their own tags
+        let on_disk = !embedded && !object_store && !Archive::is_archive(&path);
+
+        let mut errors = Vec::new();
+        let mime_types = collect(MimeTypes::new(conf), &mut errors).unwrap_or_default();
+        let inject_env = collect(EnvInjection::new(conf), &mut errors).flatten();
+        let substitute_env = collect(EnvSubstitution::new(conf), &mut errors).flatten();
+        let integrity = collect(Integrity::new(conf), &mut errors).flatten();
+        let images = collect(Images::new(conf), &mut errors).flatten();
+        let locales = collect(Locales::new(conf), &mut errors).flatten();
+
+        if embedded && integrity.is_some() {
+            errors.push("invalid integrity:\nembedded assets cannot be hashed".to_string());
+        }
+        if embedded && images.is_some() {
+            errors.push("invalid images:\nembedded assets cannot be resized".to_string());
+        }
+        if embedded && locales.is_some() {
+            errors.push("invalid locales:\nembedded assets have no language variants".to_string());
+        }
+        if images.is_some() && !cfg!(feature = "images") {
+            errors.push(
+                "the static hub resizes images, but sthub was built without the `images` feature"
+                    .to_string(),
+            );
+        }
+        if !errors.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                errors.join("\n"),
+            ));
+        }
+
+        Ok(Self {
+            precompressed: on_disk && conf.and_then(|c| c.precompressed).unwrap_or(false),
+            content_hashes: (on_disk && conf.and_then(|c| c.content_etag).unwrap_or(false))
+                .then(|| Arc::new(ContentHashes::default())),
+            listing: conf
+                .and_then(|c| c.listing.as_ref())
+                .filter(|_| !embedded)
+                .map(DirectoryListing::new),
+            remote_path,
+            path,
+            fallback,
+            embedded,
+            storage: None,
+            clean_urls: conf.and_then(|c| c.clean_urls).unwrap_or(false),
+            trailing_slash: conf
+                .and_then(|c| c.trailing_slash)
+                .unwrap_or(TrailingSlash::Leave),
+            case_insensitive: conf.and_then(|c| c.case_insensitive).unwrap_or(false),
+            index,
+            mime_types,
+            inject_env,
+            substitute_env: substitute_env.map(Arc::new),
+            releases,
+            integrity: integrity.map(Arc::new),
+            images: images.map(Arc::new),
+            locales,
+        })
+    }
+
+    /// Opens the storage the files are read from, unless they are embedded.
+    pub fn open_storage(mut self, conf: Option<&StaticHubConfiguration>) -> std::io::Result<Self> {
+        if !self.embedded {
+            self.storage = Some(storage::open(conf, &self.path)?);
+        }
+        Ok(self)
+    }
+
+    /// Whether the files are served by `actix_files` out of a local directory,
+    /// rather than through the storage.
+    pub fn serves_local_directory(&self) -> bool {
+        self.storage
+            .as_ref()
+            .is_none_or(|s| s.local_directory().is_some())
+    }
+
+    /// The first index document found in a directory of the hub.
+    pub async fn index_document(&self, dir: &Path) -> Option<PathBuf> {
+        for index in &self.index {
+            let path = dir.join(index);
+            if tokio::fs::metadata(&path).await.is_ok_and(|m| m.is_file()) {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    /// The entry served for a path relative to the remote path out of a store other than the hub
+    /// directory: the entry itself, the index document of a directory or the SPA fallback document.
+    pub fn lookup<T>(
+        &self,
+        relative: &str,
+        get: impl Fn(&str) -> Option<T>,
+        is_dir: impl Fn(&str) -> bool,
+    ) -> Option<T> {
+        let path = normalize(relative)?;
+
+        let found = match path.as_str() {
+            p if p.is_empty() || is_dir(p) => self.index.iter().find_map(|i| get(&join(p, i))),
+            p => get(p),
+        };
+
+        found.or_else(|| {
+            self.fallback
+                .as_ref()
+                .filter(|_| !is_asset(relative))
+                .and_then(|f| get(f.trim_start_matches('/')))
+        })
+    }
+
+    /// Maps a path relative to the remote path onto the hub directory,
+    /// `None` when it tries to escape it.
+    pub fn resolve(&self, relative: &str) -> Option<PathBuf> {
+        let mut path = PathBuf::from(&self.path);
+
+        for segment in relative.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                s => path.push(s),
+            }
+        }
+
+        Some(path)
+    }
+
+    /// The file served for a path relative to the remote path: the file itself, the index of a
+    /// directory or the SPA fallback document.
+    pub async fn served_file(&self, relative: &str) -> Option<PathBuf> {
+        let path = self.resolve(relative)?;
+
+        match tokio::fs::metadata(&path).await {
+            Ok(m) if m.is_dir() => self.index_document(&path).await,
+            Ok(_) => Some(path),
+            Err(_) => self
+                .fallback
+                .as_ref()
+                .filter(|_| !is_asset(relative))
+                .map(|f| Path::new(&self.path).join(f.trim_start_matches('/'))),
+        }
+    }
+
+    /// The file served out of the storage for a path relative to the remote path, with its
+    /// metadata: the file itself, the index document of a directory or the SPA fallback document.
+    pub async fn stored_file(&self, relative: &str) -> std::io::Result<Option<(String, Metadata)>> {
+        let (Some(storage), Some(path)) = (self.storage.as_ref(), normalize(relative)) else {
+            return Ok(None);
+        };
+
+        match storage.metadata(&path).await? {
+            Some(metadata) if !metadata.is_dir() => return Ok(Some((path, metadata))),
+            Some(_) => {
+                for index in &self.index {
+                    let index = join(&path, index);
+                    if let Some(metadata) = storage.metadata(&index).await?
+                        && !metadata.is_dir()
+                    {
+                        return Ok(Some((index, metadata)));
+                    }
+                }
+                // listed rather than answered with the fallback document
+                if self.listing.is_some() {
+                    return Ok(None);
+                }
+            }
+            None => {}
+        }
+
+        let fallback = self
+            .fallback
+            .as_ref()
+            .filter(|_| !is_asset(relative))
+            .map(|f| f.trim_start_matches('/'));
+        if let Some(fallback) = fallback
+            && let Some(metadata) = storage.metadata(fallback).await?
+            && !metadata.is_dir()
+        {
+            return Ok(Some((fallback.to_string(), metadata)));
+        }
+        Ok(None)
+    }
+
+    /// The path relative to the hub root of the file served for a path relative to the remote
+    /// path, whichever service serves it.
+    pub async fn served_path(&self, relative: &str) -> Option<String> {
+        match &self.storage {
+            Some(_) => match self.stored_file(relative).await {
+                Ok(file) => file.map(|(path, _)| path),
+                Err(e) => {
+                    eprintln!("failed to look up '{relative}': {e}");
+                    None
+                }
+            },
+            None if self.embedded => embedded_assets::served_path(self, relative),
+            None => None,
+        }
+    }
+
+    /// The file service serving the hub directory.
+    pub fn files(&self) -> Files {
+        let hub = self.clone();
+        let files = Files::new("", &self.path)
+            .use_last_modified(true)
+            // content hash tags are set by the `ContentEtagMiddleware` instead
+            .use_etag(self.content_hashes.is_none())
+            // dotfiles are denied by the `AccessMiddleware`, unless allowed
+            .use_hidden_files()
+            // the charset is declared by the `ContentTypeMiddleware`, following the policy
+            .prefer_utf8(false)
+            // directories are answered with their index documents, looked up in order
+            .show_files_listing()
+            .files_listing_renderer(move |dir, req| hub.render_directory(dir, req));
+
+        match self.fallback_document() {
+            Some(fallback) => {
+                files.default_handler(spa_fallback(fallback, self.mime_types.clone()))
+            }
+            // answered rather than failed, for the error pages to replace it
+            None => files.default_handler(fn_service(|req: ServiceRequest| async {
+                Ok(req.into_response(HttpResponse::NotFound().finish()))
+            })),
+        }
+    }
+
+    fn fallback_document(&self) -> Option<PathBuf> {
+        self.fallback
+            .as_ref()
+            .map(|f| Path::new(&self.path).join(f.trim_start_matches('/')))
+    }
+
+    /// Answers a directory with its first index document, its listing or the SPA fallback.
+    fn render_directory(
+        &self,
+        dir: &Directory,
+        req: &HttpRequest,
+    ) -> std::io::Result<ServiceResponse> {
+        let index = self
+            .index
+            .iter()
+            .map(|i| dir.path.join(i))
+            .find(|p| p.is_file());
+        if let Some(index) = index {
+            let file = self.mime_types.named_file(NamedFile::open(index)?);
+            return Ok(ServiceResponse::new(req.clone(), file.into_response(req)));
+        }
+
+        let fallback = self.fallback_document();
+        match &self.listing {
+            Some(listing) => listing.render(dir, req, fallback.as_deref()),
+            None => {
+                let res = match fallback {
+                    Some(fallback) => self
+                        .mime_types
+                        .named_file(NamedFile::open(fallback)?)
+                        .into_response(req),
+                    None => HttpResponse::NotFound().finish(),
+                };
+                Ok(ServiceResponse::new(req.clone(), res))
+            }
+        }
+    }
+}
+
+/// The value of an option, or `None` with its error collected.
+fn collect<T>(result: std::io::Result<T>, errors: &mut Vec<String>) -> Option<T> {
+    result.map_err(|e| errors.push(e.to_string())).ok()
+}
+
+/// The percent-decoded path relative to the hub root, without leading, trailing or repeated
+/// slashes, `None` when it tries to escape the root.
+pub fn normalize(relative: &str) -> Option<String> {
+    let decoded = percent_decode_str(relative).decode_utf8().ok()?;
+    let segments: Vec<&str> = decoded
+        .split('/')
+        .filter(|s| !s.is_empty() && *s != ".")
+        .collect();
+
+    (!segments.contains(&"..")).then(|| segments.join("/"))
+}
+
+/// Serves `document` for application routes, while missing assets still answer 404.
+fn spa_fallback(
+    document: PathBuf,
+    mime_types: MimeTypes,
+) -> impl actix_web::dev::ServiceFactory<
+    ServiceRequest,
+    Config = (),
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+    InitError = (),
+> {
+    fn_service(move |req: ServiceRequest| {
+        let (document, mime_types) = (document.clone(), mime_types.clone());
+        async move {
+            let (req, _pl) = req.into_parts();
+
+            if is_asset(req.path()) {
+                return Ok(ServiceResponse::new(req, HttpResponse::NotFound().finish()));
+            }
+
+            let res = mime_types
+                .named_file(NamedFile::open_async(&document).await?)
+                .into_response(&req);
+            Ok(ServiceResponse::new(req, res))
+        }
+    })
+}
+
+/// Whether the path targets an asset, i.e. its last segment has an extension.
+pub fn is_asset(path: &str) -> bool {
+    path.rsplit('/')
+        .next()
+        .is_some_and(|segment| segment.contains('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{self as actix_test},
+    };
+
+    #[test]
+    fn test_is_asset() {
+        assert!(is_asset("/static/app.js"));
+        assert!(is_asset("/logo.png"));
+        assert!(!is_asset("/users/42"));
+        assert!(!is_asset("/"));
+        assert!(!is_asset("/v1.2/settings"));
+    }
+
+    #[actix_web::test]
+    async fn test_spa_fallback() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        std::fs::write(dir.join("index.html"), "<html></html>").unwrap();
+
+        let conf: StaticHubConfiguration =
+            serde_yaml::from_str(&format!("path: {}\nspa: true", dir.display())).unwrap();
+        let hub = StaticHub::new(Some(&conf)).unwrap();
+
+        let app = actix_test::init_service(App::new().service(hub.files())).await;
+
+        let req = actix_test::TestRequest::get().uri("/users/42").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(actix_test::read_body(res).await, "<html></html>");
+
+        let req = actix_test::TestRequest::get()
+            .uri("/missing.js")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,412 @@
+pub mod filesystem;
+pub mod memory;
+pub mod s3;
+
+use std::{
+    convert::Infallible,
+    fmt,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::SystemTime,
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse,
+    body::{BodySize, BoxBody, MessageBody},
+    dev::{ServiceFactory, ServiceRequest, ServiceResponse, fn_service},
+    http::{
+        Method,
+        header::{self, HeaderValue, HttpDate},
+    },
+    web::Bytes,
+};
+
+use crate::{
+    core::configuration::{StaticHubConfiguration, StorageKind},
+    net::{
+        archive::Archive,
+        static_hub::{StaticHub, is_asset, normalize},
+    },
+};
+
+use self::{filesystem::FilesystemStorage, s3::S3Storage};
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + 'a>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EntryKind {
+    File,
+    Directory,
+}
+
+/// What a storage knows about a file or directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub kind: EntryKind,
+    /// In bytes, `0` for directories.
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+    /// The entity tag of the file, without quotes.
+    pub etag: Option<String>,
+}
+
+impl Metadata {
+    pub fn directory() -> Self {
+        Self {
+            kind: EntryKind::Directory,
+            size: 0,
+            modified: None,
+            etag: None,
+        }
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.kind == EntryKind::Directory
+    }
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageEntry {
+    pub name: String,
+    pub metadata: Metadata,
+}
+
+/// Where the static hub reads its files from.
+///
+/// Paths are relative to the storage root, without leading slash, `""` being the root itself.
+pub trait Storage: fmt::Debug + Send + Sync {
+    /// The file or directory at `path`, `None` when there is none.
+    fn metadata<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Option<Metadata>>;
+
+    /// The bytes of the file at `path`, or those of the inclusive `range`.
+    fn read<'a>(&'a self, path: &'a str, range: Option<(u64, u64)>) -> StorageFuture<'a, Bytes>;
+
+    /// The body of the file at `path`, or of the inclusive `range`, streamed as it is read by
+    /// the storages able to, rather than read whole.
+    fn stream<'a>(
+        &'a self,
+        path: &'a str,
+        range: Option<(u64, u64)>,
+    ) -> StorageFuture<'a, BoxBody> {
+        Box::pin(async move { Ok(BoxBody::new(self.read(path, range).await?)) })
+    }
+
+    /// The entries of the directory at `path`.
+    fn list<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Vec<StorageEntry>>;
+
+    /// The directory of a storage on the local filesystem, served by `actix_files`.
+    fn local_directory(&self) -> Option<&Path> {
+        None
+    }
+
+    /// A local tree mirroring the storage layout, for the rewrite conditions looking for files.
+    fn document_root(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Opens the storage of the static hub: an object store, an archive or a local directory.
+pub fn open(conf: Option<&StaticHubConfiguration>, path: &str) -> io::Result<Arc<dyn Storage>> {
+    let storage = conf.and_then(|c| c.storage.as_ref());
+
+    Ok(match storage {
+        Some(storage) if storage.kind == StorageKind::S3 => Arc::new(S3Storage::new(storage)?),
+        _ if Archive::is_archive(path) => Arc::new(Archive::open(Path::new(path))?),
+        _ => Arc::new(FilesystemStorage::new(path)),
+    })
+}
+
+/// Joins a directory and an entry name, the root being `""`.
+pub fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+/// The body of a `HEAD` response: the size of the file, without reading it.
+struct HeadBody(u64);
+
+impl MessageBody for HeadBody {
+    type Error = Infallible;
+
+    fn size(&self) -> BodySize {
+        BodySize::Sized(self.0)
+    }
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Bytes, Self::Error>>> {
+        Poll::Ready(None)
+    }
+}
+
+/// The body of a file or of a range of `len` bytes, only read for `GET` requests.
+async fn body(
+    req: &HttpRequest,
+    storage: &dyn Storage,
+    path: &str,
+    range: Option<(u64, u64)>,
+    len: u64,
+) -> io::Result<BoxBody> {
+    match *req.method() {
+        Method::HEAD => Ok(BoxBody::new(HeadBody(len))),
+        _ => storage.stream(path, range).await,
+    }
+}
+
+/// The single byte range requested, `Err` when it cannot be satisfied.
+/// Multiple ranges are not supported, the whole file is sent instead.
+fn byte_range(range: &str, len: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => {
+            let suffix: u64 = suffix.parse().map_err(|_| ())?;
+            if suffix == 0 {
+                return Err(());
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        (start, "") => (start.parse().map_err(|_| ())?, len.saturating_sub(1)),
+        (start, end) => {
+            let end: u64 = end.parse().map_err(|_| ())?;
+            (
+                start.parse().map_err(|_| ())?,
+                end.min(len.saturating_sub(1)),
+            )
+        }
+    };
+
+    if len == 0 || start > end || start >= len {
+        return Err(());
+    }
+    Ok(Some((start, end)))
+}
+
+async fn respond(
+    req: &HttpRequest,
+    hub: &StaticHub,
+    storage: &dyn Storage,
+    path: &str,
+    metadata: &Metadata,
+) -> io::Result<HttpResponse> {
+    let etag = metadata.etag.as_ref().map(|e| format!("\"{e}\""));
+
+    let not_modified = req
+        .headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|h| h.to_str().ok())
+        .is_some_and(|h| {
+            h.split(',').map(|t| t.trim()).any(|t| {
+                t == "*"
+                    || etag
+                        .as_deref()
+                        .is_some_and(|e| t.trim_start_matches("W/") == e)
+            })
+        });
+
+    let len = metadata.size;
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|h| h.to_str().ok())
+        .map(|h| byte_range(h, len))
+        .unwrap_or(Ok(None));
+
+    let mut res = match (not_modified, range) {
+        (true, _) => HttpResponse::NotModified().finish(),
+        (false, Err(())) => HttpResponse::RangeNotSatisfiable()
+            .insert_header((header::CONTENT_RANGE, format!("bytes */{len}")))
+            .finish(),
+        (false, Ok(Some((start, end)))) => HttpResponse::PartialContent()
+            .insert_header((header::CONTENT_TYPE, hub.mime_types.content_type(path)))
+            .insert_header((header::CONTENT_RANGE, format!("bytes {start}-{end}/{len}")))
+            .body(body(req, storage, path, Some((start, end)), end - start + 1).await?),
+        (false, Ok(None)) => HttpResponse::Ok()
+            .insert_header((header::CONTENT_TYPE, hub.mime_types.content_type(path)))
+            .body(body(req, storage, path, None, len).await?),
+    };
+
+    let headers = res.headers_mut();
+    headers.insert(header::ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+    if let Some(etag) = etag.and_then(|e| HeaderValue::from_str(&e).ok()) {
+        headers.insert(header::ETAG, etag);
+    }
+    if let Some(modified) = metadata.modified {
+        let date = HttpDate::from(modified).to_string();
+        // an HTTP date is always a valid header value
+        headers.insert(header::LAST_MODIFIED, HeaderValue::from_str(&date).unwrap());
+    }
+    Ok(res)
+}
+
+/// The file served for a missing path: the SPA fallback document of application routes.
+async fn fallback(
+    req: &HttpRequest,
+    hub: &StaticHub,
+    storage: &dyn Storage,
+    relative: &str,
+) -> io::Result<HttpResponse> {
+    let document = hub
+        .fallback
+        .as_ref()
+        .filter(|_| !is_asset(relative))
+        .map(|f| f.trim_start_matches('/'));
+
+    if let Some(document) = document
+        && let Some(metadata) = storage.metadata(document).await?
+        && !metadata.is_dir()
+    {
+        return respond(req, hub, storage, document, &metadata).await;
+    }
+    Ok(HttpResponse::NotFound().finish())
+}
+
+async fn serve(
+    req: &HttpRequest,
+    hub: &StaticHub,
+    storage: &dyn Storage,
+    relative: &str,
+) -> io::Result<HttpResponse> {
+    let Some(path) = normalize(relative) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    match storage.metadata(&path).await? {
+        Some(metadata) if !metadata.is_dir() => respond(req, hub, storage, &path, &metadata).await,
+        Some(_) => {
+            for index in &hub.index {
+                let index = join(&path, index);
+                if let Some(metadata) = storage.metadata(&index).await?
+                    && !metadata.is_dir()
+                {
+                    return respond(req, hub, storage, &index, &metadata).await;
+                }
+            }
+            match hub.listing.as_ref() {
+                Some(listing) if listing.allows(Path::new(&path)) => {
+                    listing.render_storage(storage, &path, req).await
+                }
+                _ => fallback(req, hub, storage, relative).await,
+            }
+        }
+        None => fallback(req, hub, storage, relative).await,
+    }
+}
+
+/// Serves the files of the static hub out of its storage.
+pub fn service(
+    hub: StaticHub,
+) -> impl ServiceFactory<
+    ServiceRequest,
+    Config = (),
+    Response = ServiceResponse,
+    Error = actix_web::Error,
+    InitError = (),
+> {
+    fn_service(move |req: ServiceRequest| {
+        let hub = hub.clone();
+
+        async move {
+            let res = match hub.storage.as_ref() {
+                _ if !matches!(*req.method(), Method::GET | Method::HEAD) => {
+                    HttpResponse::MethodNotAllowed()
+                        .insert_header((header::ALLOW, "GET, HEAD"))
+                        .finish()
+                }
+                Some(storage) => {
+                    serve(
+                        req.request(),
+                        &hub,
+                        storage.as_ref(),
+                        req.match_info().unprocessed(),
+                    )
+                    .await?
+                }
+                None => HttpResponse::NotFound().finish(),
+            };
+
+            let (req, _pl) = req.into_parts();
+            Ok(ServiceResponse::new(req, res))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::storage::memory::MemoryStorage;
+    use actix_web::{App, http::StatusCode, test as actix_test};
+
+    #[test]
+    fn test_byte_range() {
+        assert_eq!(byte_range("bytes=0-9", 100), Ok(Some((0, 9))));
+        assert_eq!(byte_range("bytes=90-", 100), Ok(Some((90, 99))));
+        assert_eq!(byte_range("bytes=-10", 100), Ok(Some((90, 99))));
+        assert_eq!(byte_range("bytes=50-500", 100), Ok(Some((50, 99))));
+        assert_eq!(byte_range("bytes=0-1,5-6", 100), Ok(None));
+        assert_eq!(byte_range("bytes=100-", 100), Err(()));
+        assert_eq!(byte_range("bytes=9-1", 100), Err(()));
+    }
+
+    #[actix_web::test]
+    async fn test_storage_service() {
+        let mut storage = MemoryStorage::default();
+        storage.insert("index.html", Bytes::from_static(b"<html></html>"), None);
+        storage.insert("docs/guide.txt", Bytes::from_static(b"0123456789"), None);
+
+        let conf: StaticHubConfiguration = serde_yaml::from_str("spa: true").unwrap();
+        let mut hub = StaticHub::new(Some(&conf)).unwrap();
+        hub.storage = Some(Arc::new(storage));
+        let app = actix_test::init_service(App::new().default_service(service(hub))).await;
+
+        let req = actix_test::TestRequest::get()
+            .uri("/docs/guide.txt")
+            .insert_header((header::RANGE, "bytes=2-4"))
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(actix_test::read_body(res).await, "234");
+
+        // the size of the file is sent without reading it
+        let req = actix_test::TestRequest::default()
+            .method(Method::HEAD)
+            .uri("/docs/guide.txt")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.response().body().size(), BodySize::Sized(10));
+
+        let req = actix_test::TestRequest::get().uri("/users/42").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(actix_test::read_body(res).await, "<html></html>");
+
+        // a directory without index document answers like a missing file
+        let req = actix_test::TestRequest::get().uri("/docs/").to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = actix_test::TestRequest::get()
+            .uri("/missing.js")
+            .to_request();
+        let res = actix_test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    }
+}
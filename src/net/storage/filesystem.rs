@@ -0,0 +1,96 @@
+use std::{
+    io::{self, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use actix_web::web::Bytes;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::net::storage::{EntryKind, Metadata, Storage, StorageEntry, StorageFuture};
+
+/// A directory of the local filesystem.
+#[derive(Debug)]
+pub struct FilesystemStorage {
+    root: PathBuf,
+}
+
+impl FilesystemStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+/// Files are tagged with their modification time and size, like `actix_files` does.
+fn metadata_of(metadata: &std::fs::Metadata) -> Metadata {
+    if metadata.is_dir() {
+        return Metadata::directory();
+    }
+
+    let modified = metadata.modified().ok();
+    let etag = modified
+        .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+        .map(|d| format!("{:x}-{:x}", d.as_secs(), metadata.len()));
+
+    Metadata {
+        kind: EntryKind::File,
+        size: metadata.len(),
+        modified,
+        etag,
+    }
+}
+
+impl Storage for FilesystemStorage {
+    fn metadata<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Option<Metadata>> {
+        Box::pin(async move {
+            match tokio::fs::metadata(self.resolve(path)).await {
+                Ok(metadata) => Ok(Some(metadata_of(&metadata))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e),
+            }
+        })
+    }
+
+    fn read<'a>(&'a self, path: &'a str, range: Option<(u64, u64)>) -> StorageFuture<'a, Bytes> {
+        Box::pin(async move {
+            let path = self.resolve(path);
+            let Some((start, end)) = range else {
+                return tokio::fs::read(path).await.map(Bytes::from);
+            };
+
+            let mut file = tokio::fs::File::open(path).await?;
+            file.seek(SeekFrom::Start(start)).await?;
+            let mut data = vec![0; (end - start + 1) as usize];
+            file.read_exact(&mut data).await?;
+            Ok(Bytes::from(data))
+        })
+    }
+
+    fn list<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Vec<StorageEntry>> {
+        Box::pin(async move {
+            let mut entries = Vec::new();
+            let mut dir = tokio::fs::read_dir(self.resolve(path)).await?;
+
+            while let Some(entry) = dir.next_entry().await? {
+                // symlinks are described by their target
+                let Ok(metadata) = tokio::fs::metadata(entry.path()).await else {
+                    continue;
+                };
+                entries.push(StorageEntry {
+                    name: entry.file_name().to_string_lossy().to_string(),
+                    metadata: metadata_of(&metadata),
+                });
+            }
+
+            Ok(entries)
+        })
+    }
+
+    fn local_directory(&self) -> Option<&Path> {
+        Some(&self.root)
+    }
+}
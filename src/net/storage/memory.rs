@@ -0,0 +1,160 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::SystemTime,
+};
+
+use actix_web::web::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::net::storage::{EntryKind, Metadata, Storage, StorageEntry, StorageFuture, join};
+
+/// Hex digits of the SHA-256 kept in the ETag, as for the content ETags of the static hub.
+const ETAG_HASH_LENGTH: usize = 32;
+
+#[derive(Debug)]
+struct MemoryFile {
+    data: Bytes,
+    modified: Option<SystemTime>,
+    etag: String,
+}
+
+/// Files held in memory, tagged with the hash of their content.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    files: HashMap<String, MemoryFile>,
+    directories: HashSet<String>,
+}
+
+impl MemoryStorage {
+    /// Adds a file, along with its parent directories.
+    pub fn insert(&mut self, path: &str, data: Bytes, modified: Option<SystemTime>) {
+        let mut parent = path;
+        while let Some((p, _)) = parent.rsplit_once('/') {
+            self.directories.insert(p.to_string());
+            parent = p;
+        }
+
+        let hash: String = Sha256::digest(&data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect();
+        let file = MemoryFile {
+            data,
+            modified,
+            etag: hash[..ETAG_HASH_LENGTH].to_string(),
+        };
+        self.files.insert(path.to_string(), file);
+    }
+
+    /// Adds a directory, even without files.
+    pub fn insert_directory(&mut self, path: &str) {
+        self.directories.insert(path.to_string());
+    }
+
+    pub fn directories(&self) -> impl Iterator<Item = &str> {
+        self.directories.iter().map(|d| d.as_str())
+    }
+
+    /// Every file, with its size.
+    pub fn files(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.files
+            .iter()
+            .map(|(path, f)| (path.as_str(), f.data.len() as u64))
+    }
+
+    fn metadata_of(&self, path: &str) -> Option<Metadata> {
+        if path.is_empty() || self.directories.contains(path) {
+            return Some(Metadata::directory());
+        }
+
+        self.files.get(path).map(|f| Metadata {
+            kind: EntryKind::File,
+            size: f.data.len() as u64,
+            modified: f.modified,
+            etag: Some(f.etag.clone()),
+        })
+    }
+}
+
+/// The name of `path` when it is a direct child of `dir`.
+fn child_name<'a>(dir: &str, path: &'a str) -> Option<&'a str> {
+    let name = if dir.is_empty() {
+        path
+    } else {
+        path.strip_prefix(dir)?.strip_prefix('/')?
+    };
+    (!name.is_empty() && !name.contains('/')).then_some(name)
+}
+
+impl Storage for MemoryStorage {
+    fn metadata<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Option<Metadata>> {
+        Box::pin(async move { Ok(self.metadata_of(path)) })
+    }
+
+    fn read<'a>(&'a self, path: &'a str, range: Option<(u64, u64)>) -> StorageFuture<'a, Bytes> {
+        Box::pin(async move {
+            let file = self
+                .files
+                .get(path)
+                .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+
+            Ok(match range {
+                Some((start, end)) => file.data.slice(start as usize..=end as usize),
+                None => file.data.clone(),
+            })
+        })
+    }
+
+    fn list<'a>(&'a self, path: &'a str) -> StorageFuture<'a, Vec<StorageEntry>> {
+        Box::pin(async move {
+            let names = self
+                .directories
+                .iter()
+                .chain(self.files.keys())
+                .filter_map(|p| child_name(path, p));
+
+            Ok(names
+                .filter_map(|name| {
+                    let metadata = self.metadata_of(&join(path, name))?;
+                    Some(StorageEntry {
+                        name: name.to_string(),
+                        metadata,
+                    })
+                })
+                .collect())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn test_memory_storage() {
+        let mut storage = MemoryStorage::default();
+        storage.insert("assets/app.js", Bytes::from_static(b"console.log()"), None);
+        storage.insert("index.html", Bytes::from_static(b"<html></html>"), None);
+
+        assert!(storage.metadata("assets").await.unwrap().unwrap().is_dir());
+        assert_eq!(
+            storage.metadata("index.html").await.unwrap().unwrap().size,
+            13
+        );
+        assert_eq!(storage.metadata("missing").await.unwrap(), None);
+        assert_eq!(
+            storage.read("assets/app.js", Some((0, 6))).await.unwrap(),
+            "console"
+        );
+
+        let mut names: Vec<String> = storage
+            .list("")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.name)
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["assets", "index.html"]);
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    future::{Ready, ready},
+    io,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    HttpMessage,
+    body::MessageBody,
+    dev::{Extensions, Service, ServiceRequest, ServiceResponse, Transform},
+    error::Error,
+    guard::{self, Guard},
+    web::Data,
+};
+
+use crate::{
+    core::{configuration::Configuration, rewrite::HubRules},
+    net::{
+        access_middleware::AccessPolicy,
+        admin,
+        cache_middleware::CacheRules,
+        compression_middleware::CompressionPolicies,
+        embedded_assets,
+        error_pages_middleware::ErrorPages,
+        http_adapter::HubRoutes,
+        redirect_middleware::{Redirects, host_matches},
+        releases::{self, Releases},
+        static_hub::StaticHub,
+    },
+};
+
+/// The hubs of a site and the state of its middlewares, read by the requests from the app data.
+#[derive(Clone)]
+pub struct Site {
+    pub configuration: Data<Configuration>,
+    pub routes: Data<HubRoutes>,
+    pub redirects: Data<Redirects>,
+    pub compression: Data<CompressionPolicies>,
+    pub cache_rules: Data<CacheRules>,
+    pub error_pages: Data<ErrorPages>,
+    pub access: Data<AccessPolicy>,
+    /// Always set, so that the admin API never manages the releases of the default site instead.
+    pub releases: Data<Option<Releases>>,
+}
+
+impl Site {
+    /// Fails with the first invalid option of the site, `validate` reporting all of them.
+    pub fn new(configuration: &Configuration) -> io::Result<Self> {
+        let rules = HubRules::load_validated(configuration)?;
+        let routes = HubRoutes::new(configuration, &rules)?;
+
+        Ok(Self {
+            configuration: Data::new(configuration.clone()),
+            redirects: Data::new(Redirects::new(configuration)?),
+            compression: Data::new(CompressionPolicies::new(configuration)?),
+            cache_rules: Data::new(CacheRules::new(configuration)?),
+            error_pages: Data::new(ErrorPages::new(configuration, &routes._static)?),
+            releases: Data::new(routes._static.releases.clone()),
+            routes: Data::new(routes),
+            access: Data::new(AccessPolicy::new(configuration)?),
+        })
+    }
+
+    /// The app data of the site, shadowing the one of the default site.
+    fn data(&self) -> Extensions {
+        let mut data = Extensions::new();
+        data.insert(self.configuration.clone());
+        data.insert(self.routes.clone());
+        data.insert(self.redirects.clone());
+        data.insert(self.compression.clone());
+        data.insert(self.cache_rules.clone());
+        data.insert(self.error_pages.clone());
+        data.insert(self.access.clone());
+        data.insert(self.releases.clone());
+        data
+    }
+}
+
+/// The site a request is routed to, by position in `sites`, `None` standing for the hubs of the
+/// instance.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SiteId(Option<usize>);
+
+/// Only lets the requests routed to `site` through.
+pub fn site_guard(site: SiteId) -> impl Guard {
+    guard::fn_guard(move |ctx| ctx.req_data().get::<SiteId>().copied().unwrap_or_default() == site)
+}
+
+/// The sites served for some hosts, the hubs of the instance serving the other ones.
+#[derive(Clone, Default)]
+pub struct VirtualHosts {
+    sites: Vec<(Vec<String>, Site)>,
+}
+
+impl VirtualHosts {
+    pub fn new(configuration: &Configuration) -> io::Result<Self> {
+        let mut sites = Vec::new();
+        for conf in configuration.sites.iter().flatten() {
+            let site = Site::new(&configuration.site(conf)).map_err(|e| {
+                io::Error::new(e.kind(), format!("site {}: {e}", conf.hosts.join(", ")))
+            })?;
+            let hosts = conf.hosts.iter().map(|h| h.to_ascii_lowercase()).collect();
+            sites.push((hosts, site));
+        }
+
+        Ok(Self { sites })
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = (SiteId, &Site)> {
+        self.sites
+            .iter()
+            .enumerate()
+            .map(|(i, (_, site))| (SiteId(Some(i)), site))
+    }
+
+    fn resolve(&self, host: &str) -> Option<usize> {
+        resolve(self.sites.iter().map(|(hosts, _)| hosts.as_slice()), host)
+    }
+}
+
+/// The position of the site of the most specific host matching: an exact name, then the longest
+/// `*.` domain, then `*`, the first site breaking ties.
+pub fn resolve<'a>(sites: impl IntoIterator<Item = &'a [String]>, host: &str) -> Option<usize> {
+    let mut best: Option<(usize, usize)> = None;
+
+    for (i, hosts) in sites.into_iter().enumerate() {
+        for pattern in hosts {
+            let pattern = pattern.to_ascii_lowercase();
+            let specificity = match pattern.as_str() {
+                "*" => 0,
+                p if !host_matches(p, host) => continue,
+                p if p.starts_with("*.") => p.len(),
+                _ => usize::MAX,
+            };
+            if best.is_none_or(|(b, _)| specificity > b) {
+                best = Some((specificity, i));
+            }
+        }
+    }
+
+    best.map(|(_, i)| i)
+}
+
+/// The configuration of the site serving `host`, the default site when no site matches.
+pub fn site_configuration(configuration: &Configuration, host: &str) -> Configuration {
+    let sites = configuration.sites.as_deref().unwrap_or_default();
+    match resolve(sites.iter().map(|s| s.hosts.as_slice()), host) {
+        Some(i) => configuration.site(&sites[i]),
+        None => configuration.clone(),
+    }
+}
+
+/// Every invalid option of a configuration, its sites included, reported by `--check` and
+/// before the server binds.
+pub fn validate(configuration: &Configuration) -> Vec<String> {
+    let mut errors = validate_site(configuration);
+
+    errors.extend(validate_hosts(configuration));
+    for site in configuration.sites.iter().flatten() {
+        let name = site.hosts.join(", ");
+        errors.extend(
+            validate_site(&configuration.site(site))
+                .into_iter()
+                .map(|e| format!("site {name}: {e}")),
+        );
+    }
+    if let Err(e) = admin::check(configuration) {
+        errors.push(e.to_string());
+    }
+
+    errors
+}
+
+/// The invalid options of the hubs of a site.
+fn validate_site(configuration: &Configuration) -> Vec<String> {
+    let checks: [fn(&Configuration) -> io::Result<()>; 6] = [
+        embedded_assets::check,
+        releases::check,
+        |c| Redirects::new(c).map(|_| ()),
+        |c| CompressionPolicies::new(c).map(|_| ()),
+        |c| CacheRules::new(c).map(|_| ()),
+        |c| AccessPolicy::new(c).map(|_| ()),
+    ];
+    let mut errors: Vec<String> = checks
+        .iter()
+        .filter_map(|check| check(configuration).err())
+        .map(|e| e.to_string())
+        .collect();
+
+    match HubRules::load(configuration) {
+        Ok(rules) => errors.extend(rules.validate().iter().map(|e| e.to_string())),
+        Err(e) => errors.push(e.to_string()),
+    }
+
+    // the storage is opened to look up the error page files
+    let conf = configuration.hubs.as_ref().and_then(|h| h._static.as_ref());
+    let pages = StaticHub::new(conf)
+        .and_then(|hub| hub.open_storage(conf))
+        .and_then(|hub| ErrorPages::new(configuration, &hub));
+    if let Err(e) = pages {
+        errors.push(e.to_string());
+    }
+
+    errors
+}
+
+/// The invalid hosts of the sites, and those served by several sites.
+fn validate_hosts(configuration: &Configuration) -> Vec<String> {
+    let mut errors = Vec::new();
+    let mut seen: Vec<String> = Vec::new();
+
+    for (i, site) in configuration.sites.iter().flatten().enumerate() {
+        if site.hosts.is_empty() {
+            errors.push(format!("site #{} has no host", i + 1));
+        }
+        for host in &site.hosts {
+            let host = host.to_ascii_lowercase();
+            let name = host.strip_prefix("*.").unwrap_or(&host);
+            let valid = host == "*"
+                || (!name.is_empty() && !name.contains(['*', '/', ':']) && !name.starts_with('.'));
+            if !valid {
+                errors.push(format!("invalid host '{host}'"));
+            } else if seen.contains(&host) {
+                errors.push(format!("host '{host}' belongs to several sites"));
+            }
+            seen.push(host);
+        }
+    }
+
+    errors
+}
+
+/// Fails with every invalid option of a configuration.
+pub fn check(configuration: &Configuration) -> io::Result<()> {
+    let errors = validate(configuration);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid configuration:\n{}", errors.join("\n")),
+        ))
+    }
+}
+
+/// Routes the requests to the site of their host, every middleware reading its state and only
+/// its hubs being matched.
+pub struct VirtualHostMiddleware;
+
+pub struct VirtualHostMiddlewareService<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Transform<S, ServiceRequest> for VirtualHostMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Transform = VirtualHostMiddlewareService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(VirtualHostMiddlewareService {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for VirtualHostMiddlewareService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    type Response = ServiceResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let hosts = req.app_data::<Data<VirtualHosts>>().cloned();
+        let site = hosts.as_ref().and_then(|h| {
+            let i = h.resolve(req.connection_info().host())?;
+            Some((i, h.sites[i].1.data()))
+        });
+
+        if let Some((i, data)) = site {
+            // shadows the app data of the default site for the rest of the request
+            req.add_data_container(Rc::new(data));
+            req.extensions_mut().insert(SiteId(Some(i)));
+        }
+
+        let fut = self.service.call(req);
+        Box::pin(async move { fut.await.map(|res| res.map_into_boxed_body()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+            sites:
+              - hosts: ["*"]
+              - hosts: [Example.com, "*.example.com"]
+              - hosts: ["*.docs.example.com"]
+            "#,
+        )
+        .unwrap();
+        let hosts = VirtualHosts::new(&configuration).unwrap();
+
+        assert_eq!(hosts.resolve("example.com:8080"), Some(1));
+        assert_eq!(hosts.resolve("www.example.com"), Some(1));
+        assert_eq!(hosts.resolve("v2.docs.example.com"), Some(2));
+        assert_eq!(hosts.resolve("example.org"), Some(0));
+
+        let configuration: Configuration = serde_yaml::from_str(
+            r#"
+            sites:
+              - hosts: []
+              - hosts: [a.com, "*.", "b*.com"]
+              - hosts: [A.com]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(validate(&configuration).len(), 4);
+    }
+}